@@ -2,7 +2,8 @@ use num_traits::Zero;
 use petgraph::{graph::NodeIndex, visit::EdgeRef, Graph, Undirected};
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry, BinaryHeap, VecDeque};
 use std::{f64, iter};
 
 type Predecessors = FxHashMap<NodeIndex, Vec<NodeIndex>>;
@@ -12,20 +13,20 @@ struct CB(FxHashMap<NodeIndex, f64>);
 struct BrandesNet {
     stack: Vec<NodeIndex>,
     pred: Predecessors,
-    sigma: FxHashMap<NodeIndex, usize>,
+    sigma: FxHashMap<NodeIndex, f64>,
     start: NodeIndex,
 }
 
 impl BrandesNet {
-    fn to_deltas(mut self) -> FxHashMap<NodeIndex, f64> {
+    fn into_deltas(mut self) -> FxHashMap<NodeIndex, f64> {
         let mut delta: FxHashMap<NodeIndex, f64> =
             self.stack.iter().cloned().zip(iter::repeat(0.0)).collect();
         while let Some(w) = self.stack.pop() {
-            let coeff = (1.0 + delta[&w]) / (self.sigma[&w] as f64);
+            let coeff = (1.0 + delta[&w]) / self.sigma[&w];
             for v in self.pred.get(&w).unwrap_or(&Vec::new()) {
-                let sigma_v = self.sigma[&v];
-                let delta_v = delta.get_mut(&v).unwrap();
-                *delta_v += (sigma_v as f64) * coeff;
+                let sigma_v = self.sigma[v];
+                let delta_v = delta.get_mut(v).unwrap();
+                *delta_v += sigma_v * coeff;
             }
         }
         delta.insert(self.start, 0.0);
@@ -33,16 +34,37 @@ impl BrandesNet {
     }
 }
 
+/// Normalised betweenness centrality with the edge weights as path lengths.
 pub fn betweenness_centrality<N, E>(g: &Graph<N, E, Undirected>) -> FxHashMap<NodeIndex, f64>
 where
     N: Sync,
     E: Zero + Ord + Copy + Sync,
+{
+    brandes(g, single_source_dijkstra_path)
+}
+
+/// Normalised betweenness centrality counting hops, edge weights are ignored.
+pub fn betweenness_centrality_unweighted<N, E>(
+    g: &Graph<N, E, Undirected>,
+) -> FxHashMap<NodeIndex, f64>
+where
+    N: Sync,
+    E: Sync,
+{
+    brandes(g, single_source_shortest_path)
+}
+
+fn brandes<N, E, F>(g: &Graph<N, E, Undirected>, shortest_paths: F) -> FxHashMap<NodeIndex, f64>
+where
+    N: Sync,
+    E: Sync,
+    F: Fn(&Graph<N, E, Undirected>, NodeIndex) -> BrandesNet + Sync,
 {
     let mut betweenness = CB::new(g);
     let indecies: Vec<NodeIndex> = g.node_indices().collect();
     let deltas: Vec<FxHashMap<NodeIndex, f64>> = indecies
         .par_iter()
-        .map(|s| single_source_dijkstra_path(g, *s).to_deltas())
+        .map(|s| shortest_paths(g, *s).into_deltas())
         .collect();
 
     for delta in deltas {
@@ -53,22 +75,8 @@ where
 }
 
 impl CB {
-    fn new<N, E>(graph: &Graph<N, E, Undirected>) -> CB
-    where
-        E: Zero + Ord + Copy,
-    {
-        CB(graph
-            .node_indices()
-            .zip(iter::repeat(Zero::zero()))
-            .collect())
-    }
-
-    fn accumulate_basic(&mut self, bn: BrandesNet) {
-        let delta = bn.to_deltas();
-        for w in delta.keys() {
-            let cb_w = self.inner_mut().entry(*w).or_insert(0.0);
-            *cb_w += delta[w];
-        }
+    fn new<N, E>(graph: &Graph<N, E, Undirected>) -> CB {
+        CB(graph.node_indices().zip(iter::repeat(0.0)).collect())
     }
 
     fn increment_by_deltas(&mut self, deltas: FxHashMap<NodeIndex, f64>) {
@@ -103,44 +111,41 @@ impl CB {
     }
 }
 
-//fn single_source_shortest_path<N>(
-//    g: &Graph<N, f64, Undirected>,
-//    s: NodeIndex,
-//) -> (
-//    Vec<NodeIndex>,
-//    FxHashMap<NodeIndex, Vec<NodeIndex>>,
-//    FxHashMap<NodeIndex, f64>,
-//) {
-//    let mut pred: Predecessors = g.node_indices().zip(iter::repeat_with(Vec::new)).collect();
-//
-//    let mut dist: FxHashMap<NodeIndex, f64> =
-//        g.node_indices().zip(iter::repeat(f64::INFINITY)).collect();
-//    let mut sigma: FxHashMap<NodeIndex, f64> = HashMap::new();
-//
-//    let mut queue: VecDeque<NodeIndex> = VecDeque::new();
-//    let mut stack = Vec::new();
-//
-//    sigma.insert(s, 1.0);
-//    queue.push_back(s);
-//    *dist.get_mut(&s).unwrap() = 0.0;
-//
-//    while let Some(v) = queue.pop_front() {
-//        stack.push(v);
-//        for w in g.neighbors(v) {
-//            if dist[&w] == f64::INFINITY {
-//                *dist.get_mut(&w).unwrap() = dist[&v] + 1.0;
-//                queue.push_back(w);
-//            }
-//            if dist[&w] == dist[&v] + 1.0 {
-//                let sigma_v = *sigma.get(&v).unwrap();
-//                let sigma_w = sigma.entry(w).or_insert(0.0);
-//                *sigma_w += sigma_v;
-//                pred.get_mut(&w).unwrap().push(v);
-//            }
-//        }
-//    }
-//    (stack, pred, sigma)
-//}
+fn single_source_shortest_path<N, E>(g: &Graph<N, E, Undirected>, s: NodeIndex) -> BrandesNet {
+    let mut pred: Predecessors = g.node_indices().zip(iter::repeat_with(Vec::new)).collect();
+
+    let mut dist: FxHashMap<NodeIndex, usize> = FxHashMap::default();
+    let mut sigma: FxHashMap<NodeIndex, f64> = g.node_indices().zip(iter::repeat(0.0)).collect();
+
+    let mut queue: VecDeque<NodeIndex> = VecDeque::new();
+    let mut stack = Vec::new();
+
+    sigma.insert(s, 1.0);
+    dist.insert(s, 0);
+    queue.push_back(s);
+
+    while let Some(v) = queue.pop_front() {
+        stack.push(v);
+        let dist_v = dist[&v];
+        let sigma_v = sigma[&v];
+        for w in g.neighbors(v) {
+            if let Entry::Vacant(entry) = dist.entry(w) {
+                entry.insert(dist_v + 1);
+                queue.push_back(w);
+            }
+            if dist[&w] == dist_v + 1 {
+                *sigma.get_mut(&w).unwrap() += sigma_v;
+                pred.get_mut(&w).unwrap().push(v);
+            }
+        }
+    }
+    BrandesNet {
+        stack,
+        pred,
+        sigma,
+        start: s,
+    }
+}
 
 fn single_source_dijkstra_path<N, E>(g: &Graph<N, E, Undirected>, s: NodeIndex) -> BrandesNet
 where
@@ -148,48 +153,40 @@ where
 {
     let mut pred: Predecessors = g.node_indices().zip(iter::repeat_with(Vec::new)).collect();
 
-    let mut dist: FxHashMap<NodeIndex, Option<E>> =
-        g.node_indices().zip(iter::repeat(None)).collect();
-    let mut sigma: FxHashMap<NodeIndex, usize> = FxHashMap::default();
+    let mut dist: FxHashMap<NodeIndex, E> = FxHashMap::default();
+    let mut sigma: FxHashMap<NodeIndex, f64> = g.node_indices().zip(iter::repeat(0.0)).collect();
 
     let mut seen: FxHashMap<NodeIndex, E> = FxHashMap::default();
-    let mut queue: VecDeque<(NodeIndex, NodeIndex, E)> = VecDeque::new();
+    // The counter keeps the order of equally distant nodes stable.
+    let mut queue: BinaryHeap<Reverse<(E, usize, NodeIndex, NodeIndex)>> = BinaryHeap::new();
+    let mut pushed = 0;
     let mut stack = Vec::new();
 
-    sigma.insert(s, 1);
+    sigma.insert(s, 1.0);
     seen.insert(s, Zero::zero());
-    queue.push_back((s, s, Zero::zero()));
+    queue.push(Reverse((Zero::zero(), pushed, s, s)));
 
-    while let Some((p, v, d)) = queue.pop_front() {
-        if dist[&v] != None {
+    while let Some(Reverse((d, _, p, v))) = queue.pop() {
+        if dist.contains_key(&v) {
             continue;
-        };
-        let sigma_pred = *sigma.get(&p).unwrap();
-        let sigma_v = sigma.get_mut(&v).unwrap();
-        *sigma_v += sigma_pred;
+        }
+        if p != v {
+            let sigma_pred = sigma[&p];
+            *sigma.get_mut(&v).unwrap() += sigma_pred;
+        }
         stack.push(v);
-        *dist.get_mut(&v).unwrap() = Some(d);
-        for (w, edge_weigth) in g.edges(v).map(|e| (e.target(), e.weight())) {
-            let dist_vw = d + *edge_weigth;
-            if dist[&w] == None
-                && seen
-                    .get(&w)
-                    .and_then(|seen_w| Some(dist_vw < *seen_w))
-                    .unwrap_or(true)
-            {
-                *seen.entry(w).or_insert_with(Zero::zero) = dist_vw;
-                queue.push_back((v, w, dist_vw));
-                let sigma_w = sigma.entry(w).or_insert(0);
-                *sigma_w = 0;
+        dist.insert(v, d);
+        for (w, edge_weight) in g.edges(v).map(|e| (e.target(), *e.weight())) {
+            let dist_vw = d + edge_weight;
+            if !dist.contains_key(&w) && seen.get(&w).is_none_or(|seen_w| dist_vw < *seen_w) {
+                seen.insert(w, dist_vw);
+                pushed += 1;
+                queue.push(Reverse((dist_vw, pushed, v, w)));
+                sigma.insert(w, 0.0);
                 *pred.get_mut(&w).unwrap() = vec![v];
-            } else if seen
-                .get(&w)
-                .and_then(|seen_w| Some(dist_vw == *seen_w))
-                .unwrap_or(false)
-            {
-                let sigma_v = *sigma.get(&v).unwrap();
-                let sigma_w = sigma.entry(w).or_insert(0);
-                *sigma_w += sigma_v;
+            } else if seen.get(&w) == Some(&dist_vw) {
+                let sigma_v = sigma[&v];
+                *sigma.get_mut(&w).unwrap() += sigma_v;
                 pred.get_mut(&w).unwrap().push(v);
             }
         }
@@ -201,3 +198,73 @@ where
         start: s,
     }
 }
+
+#[cfg(test)]
+fn build(n: usize, edges: &[(usize, usize, u32)]) -> Graph<(), u32, Undirected> {
+    let mut g = Graph::new_undirected();
+    let nodes: Vec<NodeIndex> = (0..n).map(|_| g.add_node(())).collect();
+    for (a, b, weight) in edges {
+        g.add_edge(nodes[*a], nodes[*b], *weight);
+    }
+    g
+}
+
+#[cfg(test)]
+fn assert_centrality(bc: FxHashMap<NodeIndex, f64>, expected: &[f64]) {
+    assert_eq!(bc.len(), expected.len());
+    for (i, expected) in expected.iter().enumerate() {
+        let actual = bc[&NodeIndex::new(i)];
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "node {}: expected {}, got {}",
+            i,
+            expected,
+            actual
+        );
+    }
+}
+
+// Reference values are the normalised ones from networkx.betweenness_centrality
+
+#[test]
+fn test_path() {
+    let g = build(4, &[(0, 1, 1), (1, 2, 1), (2, 3, 1)]);
+    let expected = [0.0, 2.0 / 3.0, 2.0 / 3.0, 0.0];
+    assert_centrality(betweenness_centrality(&g), &expected);
+    assert_centrality(betweenness_centrality_unweighted(&g), &expected);
+}
+
+#[test]
+fn test_star() {
+    let g = build(5, &[(0, 1, 3), (0, 2, 1), (0, 3, 2), (0, 4, 1)]);
+    let expected = [1.0, 0.0, 0.0, 0.0, 0.0];
+    assert_centrality(betweenness_centrality(&g), &expected);
+    assert_centrality(betweenness_centrality_unweighted(&g), &expected);
+}
+
+#[test]
+fn test_weighted_diamond_with_ties() {
+    // 0 - 1 - 3 and 0 - 2 - 3 are both of length 3
+    let g = build(4, &[(0, 1, 1), (1, 3, 2), (0, 2, 2), (2, 3, 1)]);
+    let expected = [1.0 / 6.0; 4];
+    assert_centrality(betweenness_centrality(&g), &expected);
+}
+
+#[test]
+fn test_weighted_diamond() {
+    // 0 - 1 - 3 is shorter than 0 - 2 - 3, 1 - 0 - 2 shorter than 1 - 3 - 2
+    let g = build(4, &[(0, 1, 1), (1, 3, 1), (0, 2, 1), (2, 3, 2)]);
+    assert_centrality(
+        betweenness_centrality(&g),
+        &[1.0 / 3.0, 1.0 / 3.0, 0.0, 0.0],
+    );
+    assert_centrality(betweenness_centrality_unweighted(&g), &[1.0 / 6.0; 4]);
+}
+
+#[test]
+fn test_weighted_shortcut_over_more_hops() {
+    // The direct edge 0 - 2 is longer than the detour over 1
+    let g = build(3, &[(0, 2, 5), (0, 1, 1), (1, 2, 1)]);
+    assert_centrality(betweenness_centrality(&g), &[0.0, 1.0, 0.0]);
+    assert_centrality(betweenness_centrality_unweighted(&g), &[0.0; 3]);
+}
@@ -0,0 +1,158 @@
+use git2::{Oid, Repository, Tree};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use std::path::Path;
+
+use super::path_filter::glob_to_regex;
+
+/// The attributes that mark a file as not worth looking at for coupling.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Attribute {
+    Generated,
+    Vendored,
+    Diff,
+}
+
+/// A line of a .gitattributes file, set to `Some(true)`, unset to
+/// `Some(false)` or back to unspecified with `None`.
+struct Rule {
+    pattern: Regex,
+    assignments: Vec<(Attribute, Option<bool>)>,
+}
+
+/// The .gitattributes files of the commits' trees, parsed once per blob.
+///
+/// libgit2 1.3 only checks attributes against the work tree and the index, so
+/// the files are read from the tree of every commit instead.
+#[derive(Default)]
+pub struct Attributes {
+    rules: FxHashMap<Oid, Vec<Rule>>,
+}
+
+impl Attributes {
+    /// Whether `path` is marked `linguist-generated`, `linguist-vendored` or
+    /// `-diff` by the .gitattributes files in `tree`.
+    pub fn is_ignored(&mut self, repo: &Repository, tree: &Tree, path: &str) -> bool {
+        let mut state = [None; 3];
+        let components: Vec<&str> = path.split('/').collect();
+        // Deeper files override the ones closer to the root.
+        for depth in 0..components.len() {
+            let directory = components[..depth].join("/");
+            let (file, relative) = if directory.is_empty() {
+                (".gitattributes".to_string(), path)
+            } else {
+                (
+                    format!("{}/.gitattributes", directory),
+                    &path[directory.len() + 1..],
+                )
+            };
+            let entry = match tree.get_path(Path::new(&file)) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let rules = self
+                .rules
+                .entry(entry.id())
+                .or_insert_with(|| read_rules(repo, entry.id()));
+            apply(rules, relative, &mut state);
+        }
+        is_ignored(&state)
+    }
+}
+
+fn apply(rules: &[Rule], path: &str, state: &mut [Option<bool>; 3]) {
+    for rule in rules.iter().filter(|rule| rule.pattern.is_match(path)) {
+        for (attribute, value) in &rule.assignments {
+            state[*attribute as usize] = *value;
+        }
+    }
+}
+
+fn is_ignored(state: &[Option<bool>; 3]) -> bool {
+    state[Attribute::Generated as usize] == Some(true)
+        || state[Attribute::Vendored as usize] == Some(true)
+        || state[Attribute::Diff as usize] == Some(false)
+}
+
+fn read_rules(repo: &Repository, blob: Oid) -> Vec<Rule> {
+    repo.find_blob(blob)
+        .map(|blob| parse_rules(&String::from_utf8_lossy(blob.content())))
+        .unwrap_or_default()
+}
+
+fn parse_rules(content: &str) -> Vec<Rule> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let pattern = tokens.next().filter(|pattern| !pattern.starts_with('#'))?;
+            let assignments: Vec<(Attribute, Option<bool>)> =
+                tokens.filter_map(assignment).collect();
+            if assignments.is_empty() {
+                return None;
+            }
+            Some(Rule {
+                pattern: Regex::new(&glob_to_regex(pattern, false)).ok()?,
+                assignments,
+            })
+        })
+        .collect()
+}
+
+fn assignment(token: &str) -> Option<(Attribute, Option<bool>)> {
+    let (name, value) = if let Some(name) = token.strip_prefix('-') {
+        (name, Some(false))
+    } else if let Some(name) = token.strip_prefix('!') {
+        (name, None)
+    } else {
+        match token.split_once('=') {
+            Some((name, value)) => (name, Some(value != "false")),
+            None => (token, Some(true)),
+        }
+    };
+    match name {
+        "linguist-generated" => Some((Attribute::Generated, value)),
+        "linguist-vendored" => Some((Attribute::Vendored, value)),
+        "diff" => Some((Attribute::Diff, value)),
+        // A macro for -diff -merge -text
+        "binary" if value == Some(true) => Some((Attribute::Diff, Some(false))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn ignored_by(content: &str, path: &str) -> bool {
+    let mut state = [None; 3];
+    apply(&parse_rules(content), path, &mut state);
+    is_ignored(&state)
+}
+
+#[test]
+fn test_parse_rules() {
+    let content = "# comment\n*.lock -diff\nvendor/** linguist-vendored\n\
+                   gen/*.rs linguist-generated=true\n*.png binary\n*.rs text eol=lf\n";
+
+    assert_eq!(parse_rules(content).len(), 4);
+    assert!(ignored_by(content, "web/yarn.lock"));
+    assert!(ignored_by(content, "vendor/lib/a.js"));
+    assert!(!ignored_by(content, "web/vendor/a.js"));
+    assert!(ignored_by(content, "gen/api.rs"));
+    assert!(!ignored_by(content, "gen/sub/api.rs"));
+    assert!(ignored_by(content, "img/logo.png"));
+    assert!(!ignored_by(content, "src/main.rs"));
+}
+
+#[test]
+fn test_later_rules_override() {
+    let content = "vendor/** linguist-vendored\nvendor/ours/** -linguist-vendored\n\
+                   *.js linguist-generated\nsrc.js !linguist-generated\n\
+                   *.svg -diff\nicon.svg diff=xml\n*.min.js linguist-generated=false\n";
+
+    assert!(ignored_by(content, "vendor/theirs/a.rb"));
+    assert!(!ignored_by(content, "vendor/ours/a.rb"));
+    assert!(ignored_by(content, "lib/app.js"));
+    assert!(!ignored_by(content, "lib/src.js"));
+    assert!(!ignored_by(content, "lib/app.min.js"));
+    assert!(ignored_by(content, "logo.svg"));
+    assert!(!ignored_by(content, "assets/icon.svg"));
+}
@@ -0,0 +1,123 @@
+use std::{fmt, io, path::PathBuf};
+
+use super::store::FORMAT_VERSION;
+
+/// Everything that can go wrong between opening the repo and writing the
+/// results.
+#[derive(Debug)]
+pub enum RorqualError {
+    /// The path is not inside a git repository.
+    NotARepo {
+        path: PathBuf,
+        source: git2::Error,
+    },
+    /// A date argument could not be parsed.
+    BadDate {
+        input: String,
+        reason: String,
+    },
+    /// A revision or range that git can't resolve to commits.
+    UnknownRevision {
+        spec: String,
+        source: git2::Error,
+    },
+    /// A path was asked for that the graph has no node for.
+    UnknownPath(String),
+    /// The walk selected no commits to build the graph from.
+    EmptyHistory,
+    /// A saved scan was written in another version of the file format.
+    IncompatibleScan {
+        path: PathBuf,
+        version: u32,
+    },
+    /// A file that should be a saved scan can't be read as one.
+    InvalidScan {
+        path: PathBuf,
+        reason: String,
+    },
+    InvalidRegex(regex::Error),
+    Git(git2::Error),
+    Io(io::Error),
+}
+
+impl RorqualError {
+    /// The exit code of the CLI, distinct for every kind of bad input.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RorqualError::Git(_) | RorqualError::Io(_) => 1,
+            RorqualError::NotARepo { .. } => 2,
+            RorqualError::BadDate { .. } => 3,
+            RorqualError::UnknownPath(_) => 4,
+            RorqualError::EmptyHistory => 5,
+            RorqualError::InvalidRegex(_) => 6,
+            RorqualError::UnknownRevision { .. } => 7,
+            RorqualError::IncompatibleScan { .. } => 8,
+            RorqualError::InvalidScan { .. } => 9,
+        }
+    }
+}
+
+impl fmt::Display for RorqualError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RorqualError::NotARepo { path, source } => write!(
+                f,
+                "{} is not inside a git repository: {}",
+                path.display(),
+                source.message()
+            ),
+            RorqualError::BadDate { input, reason } => {
+                write!(f, "can't read \"{}\" as a date: {}", input, reason)
+            }
+            RorqualError::UnknownRevision { spec, source } => {
+                write!(f, "unknown revision {}: {}", spec, source.message())
+            }
+            RorqualError::UnknownPath(path) => write!(f, "{} is not part of the graph", path),
+            RorqualError::EmptyHistory => write!(f, "no commits left to build the graph from"),
+            RorqualError::IncompatibleScan { path, version } => write!(
+                f,
+                "{} was saved in format version {}, this version of rorqual reads {}, scan again",
+                path.display(),
+                version,
+                FORMAT_VERSION
+            ),
+            RorqualError::InvalidScan { path, reason } => {
+                write!(f, "{} is not a saved scan: {}", path.display(), reason)
+            }
+            RorqualError::InvalidRegex(e) => write!(f, "invalid regex: {}", e),
+            RorqualError::Git(e) => write!(f, "git: {}", e.message()),
+            RorqualError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RorqualError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RorqualError::NotARepo { source, .. } => Some(source),
+            RorqualError::UnknownRevision { source, .. } => Some(source),
+            RorqualError::InvalidRegex(e) => Some(e),
+            RorqualError::Git(e) => Some(e),
+            RorqualError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for RorqualError {
+    fn from(e: git2::Error) -> Self {
+        RorqualError::Git(e)
+    }
+}
+
+impl From<regex::Error> for RorqualError {
+    fn from(e: regex::Error) -> Self {
+        RorqualError::InvalidRegex(e)
+    }
+}
+
+impl From<io::Error> for RorqualError {
+    fn from(e: io::Error) -> Self {
+        RorqualError::Io(e)
+    }
+}
@@ -0,0 +1,624 @@
+//! Writers for the graph formats of the CLI.
+use chrono::{SecondsFormat, TimeZone, Utc};
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph_graphml::GraphMl;
+use rustc_hash::FxHashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::{self, Write},
+};
+
+use super::git_graph::{GitGraph, Similarity, Weighting};
+use super::neighbourhood::NeighbourQuery;
+
+/// Writes the graph as GraphML with the path, status, commit count and, if
+/// given, centrality of every file and the co-change count and weight of
+/// every edge.
+pub fn write_graphml<W: Write>(
+    graph: &GitGraph,
+    weighting: Weighting,
+    centrality: Option<&FxHashMap<NodeIndex, f64>>,
+    writer: &mut W,
+) -> io::Result<()> {
+    let attributes = graph.graph.map(
+        |idx, file| (file, centrality.map(|bc| bc[&idx])),
+        |edge, co_change| (co_change.count, graph.similarity(edge, weighting).0),
+    );
+    let graphml = GraphMl::new(&attributes)
+        .pretty_print(true)
+        .export_node_weights(Box::new(|(file, centrality)| {
+            let mut data = vec![
+                ("path".into(), file.name.as_str().into()),
+                ("status".into(), file.status.to_string().into()),
+                ("commits".into(), file.commits.to_string().into()),
+            ];
+            if let Some(centrality) = centrality {
+                data.push(("centrality".into(), centrality.to_string().into()));
+            }
+            data
+        }))
+        .export_edge_weights(Box::new(|(count, weight)| {
+            vec![
+                ("count".into(), count.to_string().into()),
+                ("weight".into(), weight.to_string().into()),
+            ]
+        }));
+
+    // petgraph-graphml declares every attribute as a string, but Gephi can
+    // only size and colour by numeric ones.
+    let mut xml = Vec::new();
+    graphml.to_writer(&mut xml)?;
+    let mut xml = String::from_utf8(xml).unwrap();
+    for (key, kind) in &[
+        ("commits", "int"),
+        ("centrality", "double"),
+        ("count", "int"),
+        ("weight", "double"),
+    ] {
+        xml = xml.replace(
+            &format!("attr.name=\"{}\" attr.type=\"string\"", key),
+            &format!("attr.name=\"{}\" attr.type=\"{}\"", key, kind),
+        );
+    }
+    // It also declares them in hash order, sorted they stay the same between
+    // runs.
+    let mut lines: Vec<&str> = xml.lines().collect();
+    let is_key = |line: &str| line.trim_start().starts_with("<key ");
+    if let Some(first) = lines.iter().position(|line| is_key(line)) {
+        let count = lines[first..]
+            .iter()
+            .take_while(|line| is_key(line))
+            .count();
+        lines[first..first + count].sort_unstable();
+    }
+    writeln!(writer, "{}", lines.join("\n"))
+}
+
+/// Writes the whole graph for Graphviz with files grouped into clusters by
+/// their first `depth` directories.
+pub fn write_dot<W: Write>(
+    graph: &GitGraph,
+    weighting: Weighting,
+    depth: usize,
+    min_weight: f64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let (_, max_weight) = graph.similarity_range(weighting);
+    let mut clusters: BTreeMap<String, Vec<NodeIndex>> = BTreeMap::new();
+    for idx in graph.graph.node_indices() {
+        let directory = directory_prefix(&graph.graph[idx].name, depth);
+        clusters.entry(directory).or_default().push(idx);
+    }
+
+    writeln!(writer, "graph {{")?;
+    for (i, (directory, nodes)) in clusters.iter().enumerate() {
+        if directory.is_empty() {
+            for idx in nodes {
+                write_node(*idx, graph, writer)?;
+            }
+            continue;
+        }
+        writeln!(writer, "subgraph cluster_{} {{", i)?;
+        writeln!(writer, "label=\"{}\"", dot_escape(directory))?;
+        for idx in nodes {
+            write_node(*idx, graph, writer)?;
+        }
+        writeln!(writer, "}}")?;
+    }
+    for edge in graph.graph.edge_indices() {
+        if graph.similarity(edge, weighting).0 < min_weight {
+            continue;
+        }
+        let (from, to) = graph.graph.edge_endpoints(edge).unwrap();
+        write_edge(from, to, graph, weighting, max_weight, writer)?;
+    }
+    writeln!(writer, "}}")
+}
+
+fn directory_prefix(path: &str, depth: usize) -> String {
+    let directories: Vec<&str> = path.split('/').collect();
+    directories[..directories.len() - 1]
+        .iter()
+        .take(depth)
+        .copied()
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+fn write_node<W: Write>(idx: NodeIndex, graph: &GitGraph, writer: &mut W) -> io::Result<()> {
+    let name = &graph.graph[idx].name;
+    writeln!(
+        writer,
+        "\"{:?}\" [label=\"{}\" fixedsize=true fontsize=7]",
+        idx,
+        dot_escape(name)
+    )
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_edge<W: Write>(
+    from: NodeIndex,
+    to: NodeIndex,
+    graph: &GitGraph,
+    weighting: Weighting,
+    max_weight: Similarity,
+    writer: &mut W,
+) -> io::Result<()> {
+    let edge_idx = graph.graph.find_edge(from, to).unwrap();
+    let Similarity(weight) = graph.similarity(edge_idx, weighting);
+    writeln!(
+        writer,
+        "\"{:?}\" -- \"{:?}\" [weight={} penwidth={:.2}]",
+        from,
+        to,
+        weight,
+        1.0 + 4.0 * weight / max_weight.0
+    )
+}
+
+/// Writes a dynamic GEXF graph for Gephi's timeline, cut into time slices of
+/// `interval` seconds. Edges are active in every slice with a shared change
+/// and carry the share of their `weighting` weight that the slice's shared
+/// changes make up. Files are active in the slices of their first and last
+/// change and in those of their edges.
+pub fn write_gexf<W: Write>(
+    graph: &GitGraph,
+    weighting: Weighting,
+    interval: i64,
+    writer: &mut W,
+) -> io::Result<()> {
+    if interval <= 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the gexf interval has to be positive, not {}", interval),
+        ));
+    }
+    let slice_start = |time: i64| time - time.rem_euclid(interval);
+
+    let mut node_slices: Vec<BTreeSet<i64>> = graph
+        .graph
+        .node_weights()
+        .map(|file| {
+            [file.first_change, file.last_change]
+                .iter()
+                .map(|time| slice_start(*time))
+                .collect()
+        })
+        .collect();
+    let mut edge_slices = Vec::with_capacity(graph.graph.edge_count());
+    for edge in graph.graph.edge_indices() {
+        let mut slices: BTreeMap<i64, u32> = BTreeMap::new();
+        for time in &graph.graph[edge].times {
+            *slices.entry(slice_start(*time)).or_insert(0) += 1;
+        }
+        let (source, target) = graph.graph.edge_endpoints(edge).unwrap();
+        for node in &[source, target] {
+            node_slices[node.index()].extend(slices.keys());
+        }
+        edge_slices.push(slices);
+    }
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<gexf xmlns="http://www.gexf.net/1.2draft" version="1.2">"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <graph mode="dynamic" defaultedgetype="undirected" timeformat="dateTime">"#
+    )?;
+    writeln!(writer, r#"    <attributes class="node" mode="static">"#)?;
+    writeln!(
+        writer,
+        r#"      <attribute id="status" title="status" type="string"/>"#
+    )?;
+    writeln!(
+        writer,
+        r#"      <attribute id="commits" title="commits" type="integer"/>"#
+    )?;
+    writeln!(writer, r#"    </attributes>"#)?;
+    writeln!(writer, r#"    <attributes class="edge" mode="static">"#)?;
+    writeln!(
+        writer,
+        r#"      <attribute id="count" title="count" type="integer"/>"#
+    )?;
+    writeln!(writer, r#"    </attributes>"#)?;
+    writeln!(writer, r#"    <attributes class="edge" mode="dynamic">"#)?;
+    writeln!(
+        writer,
+        r#"      <attribute id="weight" title="weight" type="float"/>"#
+    )?;
+    writeln!(writer, r#"    </attributes>"#)?;
+
+    writeln!(writer, r#"    <nodes>"#)?;
+    for (idx, slices) in graph.graph.node_indices().zip(&node_slices) {
+        let file = &graph.graph[idx];
+        writeln!(
+            writer,
+            r#"      <node id="n{}" label="{}">"#,
+            idx.index(),
+            xml_escape(&file.name)
+        )?;
+        writeln!(writer, r#"        <attvalues>"#)?;
+        writeln!(
+            writer,
+            r#"          <attvalue for="status" value="{}"/>"#,
+            file.status
+        )?;
+        writeln!(
+            writer,
+            r#"          <attvalue for="commits" value="{}"/>"#,
+            file.commits
+        )?;
+        writeln!(writer, r#"        </attvalues>"#)?;
+        write_spells(slices.iter().copied(), interval, writer)?;
+        writeln!(writer, r#"      </node>"#)?;
+    }
+    writeln!(writer, r#"    </nodes>"#)?;
+
+    writeln!(writer, r#"    <edges>"#)?;
+    for (edge, slices) in graph.graph.edge_indices().zip(&edge_slices) {
+        let (source, target) = graph.graph.edge_endpoints(edge).unwrap();
+        let Similarity(weight) = graph.similarity(edge, weighting);
+        let shared = graph.graph[edge].times.len() as f64;
+        writeln!(
+            writer,
+            r#"      <edge id="e{}" source="n{}" target="n{}" weight="{}">"#,
+            edge.index(),
+            source.index(),
+            target.index(),
+            weight
+        )?;
+        writeln!(writer, r#"        <attvalues>"#)?;
+        writeln!(
+            writer,
+            r#"          <attvalue for="count" value="{}"/>"#,
+            graph.graph[edge].count
+        )?;
+        for (start, count) in slices {
+            writeln!(
+                writer,
+                r#"          <attvalue for="weight" value="{}" start="{}" end="{}"/>"#,
+                weight * f64::from(*count) / shared,
+                gexf_date(*start)?,
+                gexf_date(start.saturating_add(interval))?
+            )?;
+        }
+        writeln!(writer, r#"        </attvalues>"#)?;
+        write_spells(slices.keys().copied(), interval, writer)?;
+        writeln!(writer, r#"      </edge>"#)?;
+    }
+    writeln!(writer, r#"    </edges>"#)?;
+    writeln!(writer, r#"  </graph>"#)?;
+    writeln!(writer, r#"</gexf>"#)
+}
+
+/// Writes a spell for every slice starting at one of `starts`.
+fn write_spells<W: Write>(
+    starts: impl Iterator<Item = i64>,
+    interval: i64,
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(writer, r#"        <spells>"#)?;
+    for start in starts {
+        writeln!(
+            writer,
+            r#"          <spell start="{}" end="{}"/>"#,
+            gexf_date(start)?,
+            gexf_date(start.saturating_add(interval))?
+        )?;
+    }
+    writeln!(writer, r#"        </spells>"#)
+}
+
+fn gexf_date(time: i64) -> io::Result<String> {
+    match Utc.timestamp_opt(time, 0).single() {
+        Some(date) => Ok(date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} seconds since the epoch is out of range for a date",
+                time
+            ),
+        )),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the subgraph induced by `inside` and, greyed out and dashed, the
+/// edges leaving it together with the files they lead to.
+pub fn write_neighbourhood<W: Write>(
+    graph: &GitGraph,
+    query: &NeighbourQuery,
+    inside: &HashSet<NodeIndex>,
+    writer: &mut W,
+) -> io::Result<()> {
+    let (_, max_weight) = graph.similarity_range(query.weighting);
+    let mut nodes: Vec<NodeIndex> = inside.iter().copied().collect();
+    nodes.sort();
+    // Only the edges the walk may follow, from either end if both are inside.
+    let edges: BTreeSet<EdgeIndex> = nodes
+        .iter()
+        .flat_map(|idx| query.edges(graph, *idx))
+        .map(|(edge, _)| edge)
+        .collect();
+    let mut inside_edges = Vec::new();
+    let mut boundary_edges = Vec::new();
+    for edge in edges {
+        let (from, to) = graph.graph.edge_endpoints(edge).unwrap();
+        match (inside.contains(&from), inside.contains(&to)) {
+            (true, true) => inside_edges.push((from, to)),
+            (true, false) | (false, true) => boundary_edges.push((from, to)),
+            (false, false) => {}
+        }
+    }
+    let mut outside: Vec<NodeIndex> = boundary_edges
+        .iter()
+        .flat_map(|(from, to)| vec![*from, *to])
+        .filter(|idx| !inside.contains(idx))
+        .collect();
+    outside.sort();
+    outside.dedup();
+
+    writeln!(writer, "graph {{")?;
+    for idx in nodes {
+        write_node(idx, graph, writer)?;
+    }
+    for (from, to) in inside_edges {
+        write_edge(from, to, graph, query.weighting, max_weight, writer)?;
+    }
+    writeln!(writer, "subgraph boundary {{")?;
+    writeln!(writer, "node [color=grey fontcolor=grey]")?;
+    writeln!(writer, "edge [style=dashed color=grey]")?;
+    for idx in outside {
+        write_node(idx, graph, writer)?;
+    }
+    for (from, to) in boundary_edges {
+        write_edge(from, to, graph, query.weighting, max_weight, writer)?;
+    }
+    writeln!(writer, "}}")?;
+    writeln!(writer, "}}")
+}
+
+#[cfg(test)]
+use super::git_graph::{build_graph, ChangeSet, GitFile, GraphOptions, Status};
+
+/// A graph of changesets of the given files at the given times.
+#[cfg(test)]
+fn graph_of(changesets: &[(i64, &[&str])]) -> GitGraph {
+    let changesets = changesets
+        .iter()
+        .map(|(time, names)| ChangeSet {
+            time: *time,
+            files: names
+                .iter()
+                .map(|name| GitFile {
+                    status: Status::Modified,
+                    name: name.to_string(),
+                })
+                .collect(),
+        })
+        .collect();
+    build_graph(changesets, &GraphOptions::default())
+}
+
+#[cfg(test)]
+fn written<F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(write: F) -> String {
+    let mut bytes = Vec::new();
+    write(&mut bytes).unwrap();
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn test_write_graphml() {
+    let graph = graph_of(&[
+        (0, &["src/a.rs", "src/b.rs"]),
+        (0, &["src/a.rs", "src/b.rs"]),
+        (0, &["src/a.rs", "README.md"]),
+    ]);
+    let mut centrality = FxHashMap::default();
+    for idx in graph.graph.node_indices() {
+        centrality.insert(idx, 0.0);
+    }
+    centrality.insert(graph.node("src/a.rs").unwrap(), 0.5);
+    let xml = written(|out| write_graphml(&graph, Weighting::Count, Some(&centrality), out));
+
+    let keys: Vec<&str> = xml
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("<key "))
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            r#"<key id="centrality" for="node" attr.name="centrality" attr.type="double" />"#,
+            r#"<key id="commits" for="node" attr.name="commits" attr.type="int" />"#,
+            r#"<key id="count" for="edge" attr.name="count" attr.type="int" />"#,
+            r#"<key id="path" for="node" attr.name="path" attr.type="string" />"#,
+            r#"<key id="status" for="node" attr.name="status" attr.type="string" />"#,
+            r#"<key id="weight" for="edge" attr.name="weight" attr.type="double" />"#,
+        ]
+    );
+    let data: Vec<&str> = xml
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("<data "))
+        .collect();
+    let a = data
+        .iter()
+        .position(|line| *line == r#"<data key="path">src/a.rs</data>"#)
+        .unwrap();
+    assert_eq!(
+        data[a + 1..a + 4],
+        [
+            r#"<data key="status">modified</data>"#,
+            r#"<data key="commits">3</data>"#,
+            r#"<data key="centrality">0.5</data>"#,
+        ]
+    );
+    assert!(data.windows(2).any(|pair| pair
+        == [
+            r#"<data key="count">2</data>"#,
+            r#"<data key="weight">2</data>"#
+        ]));
+    assert!(data.windows(2).any(|pair| pair
+        == [
+            r#"<data key="count">1</data>"#,
+            r#"<data key="weight">1</data>"#
+        ]));
+}
+
+#[test]
+fn test_write_gexf() {
+    const DAY: i64 = 24 * 60 * 60;
+    let graph = graph_of(&[
+        (2 * DAY, &["a", "b", "c"]),
+        (100, &["a", "b"]),
+        (0, &["a", "b"]),
+    ]);
+    let gexf = |weighting| written(|out| write_gexf(&graph, weighting, DAY, out));
+    let xml = gexf(Weighting::Count);
+    let lines: Vec<&str> = xml.lines().map(str::trim).collect();
+    let element = |start: &str| {
+        let first = lines
+            .iter()
+            .position(|line| line.starts_with(start))
+            .unwrap();
+        let end = if start.starts_with("<node") {
+            "</node>"
+        } else {
+            "</edge>"
+        };
+        let last = first + lines[first..].iter().position(|line| *line == end).unwrap();
+        lines[first..=last].to_vec()
+    };
+    let node = |name: &str| {
+        element(&format!(
+            r#"<node id="n{}""#,
+            graph.node(name).unwrap().index()
+        ))
+    };
+    let edge = |a: &str, b: &str| {
+        let idx = graph
+            .graph
+            .find_edge(graph.node(a).unwrap(), graph.node(b).unwrap())
+            .unwrap();
+        element(&format!(r#"<edge id="e{}""#, idx.index()))
+    };
+    let spells = |element: Vec<&str>| -> Vec<String> {
+        element
+            .into_iter()
+            .filter(|line| line.starts_with("<spell "))
+            .map(String::from)
+            .collect()
+    };
+    let weights = |element: Vec<&str>| -> Vec<String> {
+        element
+            .into_iter()
+            .filter(|line| line.starts_with(r#"<attvalue for="weight""#))
+            .map(String::from)
+            .collect()
+    };
+
+    assert!(xml.contains(
+        r#"<attributes class="edge" mode="dynamic">
+      <attribute id="weight" title="weight" type="float"/>"#
+    ));
+    assert_eq!(
+        spells(node("a")),
+        [
+            r#"<spell start="1970-01-01T00:00:00Z" end="1970-01-02T00:00:00Z"/>"#,
+            r#"<spell start="1970-01-03T00:00:00Z" end="1970-01-04T00:00:00Z"/>"#,
+        ]
+    );
+    assert_eq!(
+        spells(node("c")),
+        [r#"<spell start="1970-01-03T00:00:00Z" end="1970-01-04T00:00:00Z"/>"#]
+    );
+    assert_eq!(spells(edge("a", "b")), spells(node("a")));
+    assert_eq!(
+        weights(edge("a", "b")),
+        [
+            r#"<attvalue for="weight" value="2" start="1970-01-01T00:00:00Z" end="1970-01-02T00:00:00Z"/>"#,
+            r#"<attvalue for="weight" value="1" start="1970-01-03T00:00:00Z" end="1970-01-04T00:00:00Z"/>"#,
+        ]
+    );
+
+    let xml = gexf(Weighting::Confidence);
+    assert!(xml.contains(
+        r#"<attvalue for="weight" value="0.6666666666666666" start="1970-01-01T00:00:00Z" end="1970-01-02T00:00:00Z"/>"#
+    ));
+    assert!(xml.contains(
+        r#"<attvalue for="weight" value="0.3333333333333333" start="1970-01-03T00:00:00Z" end="1970-01-04T00:00:00Z"/>"#
+    ));
+
+    let mut out = Vec::new();
+    assert!(write_gexf(&graph, Weighting::Count, 0, &mut out).is_err());
+}
+
+#[test]
+fn test_write_dot() {
+    let graph = graph_of(&[
+        (0, &["src/a/x.rs", "src/a/y.rs"]),
+        (0, &["src/a/x.rs", "src/a/y.rs"]),
+        (0, &["src/a/x.rs", "src/b/z.rs"]),
+        (0, &["top.rs", r#"d"o\c/"q".md"#]),
+    ]);
+    let dot = |depth, min_weight| {
+        written(|out| write_dot(&graph, Weighting::Count, depth, min_weight, out))
+    };
+    let matching = |dot: &str, start: &str| -> Vec<String> {
+        dot.lines()
+            .filter(|line| line.starts_with(start))
+            .map(String::from)
+            .collect()
+    };
+    let label = |name: &str| {
+        format!(
+            r#""{:?}" [label="{}" fixedsize=true fontsize=7]"#,
+            graph.node(name).unwrap(),
+            name.replace('\\', r"\\").replace('"', r#"\""#)
+        )
+    };
+
+    let shallow = dot(1, 0.0);
+    assert_eq!(
+        matching(&shallow, "label="),
+        [r#"label="d\"o\\c""#, r#"label="src""#]
+    );
+    assert_eq!(matching(&shallow, "subgraph cluster_").len(), 2);
+    assert_eq!(matching(&shallow, "\"NodeIndex").len(), 5 + 3);
+    assert!(shallow.contains(&label(r#"d"o\c/"q".md"#)));
+    // Files outside any directory come before the clusters.
+    assert!(shallow.find(&label("top.rs")) < shallow.find("subgraph"));
+
+    let deep = dot(2, 0.0);
+    assert_eq!(
+        matching(&deep, "label="),
+        [r#"label="d\"o\\c""#, r#"label="src/a""#, r#"label="src/b""#]
+    );
+
+    let heavy = dot(1, 2.0);
+    assert_eq!(
+        matching(&heavy, "\"NodeIndex")
+            .iter()
+            .filter(|line| line.contains(" -- "))
+            .collect::<Vec<_>>(),
+        [&format!(
+            r#""{:?}" -- "{:?}" [weight=2 penwidth=5.00]"#,
+            graph.node("src/a/x.rs").unwrap(),
+            graph.node("src/a/y.rs").unwrap()
+        )]
+    );
+}
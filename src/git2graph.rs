@@ -1,105 +1,867 @@
-use chrono::format::{self, strftime::StrftimeItems, Parsed};
-use chrono::{DateTime, Utc};
-use git2::{Delta, DiffDelta, DiffOptions, Repository, Sort, Tree};
-use petgraph::{graph::NodeIndex, Graph, Undirected};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use regex::RegexSet;
-use std::error::Error;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use git2::{
+    Commit, Delta, DiffDelta, DiffFile, DiffFindOptions, DiffOptions, ErrorCode, Mailmap, Object,
+    Oid, Repository, RevparseMode, Revwalk, Signature, Sort, Tree,
+};
+use rayon::prelude::*;
+use regex::{Regex, RegexSet};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{
+    collections::{hash_map::Entry, BTreeMap},
+    convert::TryFrom,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-use std::collections::{HashMap, HashSet};
+const IGNORE_FILE: &str = ".rorqualignore";
+const IGNORE_REVS_FILE: &str = ".git-blame-ignore-revs";
 
+#[derive(Clone)]
 pub struct GitFilter {
     pub start_date: Option<DateTime<Utc>>,
+    pub end_date: Option<DateTime<Utc>>,
+    /// Revisions like `v1.3`, ranges like `v1.2..v1.3` or ref globs like
+    /// `refs/heads/release/*` to walk instead of HEAD, a leading `^` excludes
+    /// the commits reachable from a revision or glob.
+    pub revisions: Vec<String>,
+    /// Stop the walk after this many selected commits.
     pub max_commits: Option<u32>,
-    pub path_filters: RegexSet,
+    pub path_filter: PathFilter,
+    /// Leave out the files the .gitattributes of each commit mark as
+    /// `linguist-generated`, `linguist-vendored` or `-diff`, and the ones
+    /// matched by the repo's `.rorqualignore`.
+    pub skip_ignored: bool,
+    pub merge_diff: MergeDiff,
+    pub selection: CommitSelection,
+    /// Rules over the authors and committers of the commits.
+    pub identities: Vec<IdentityRule>,
+    /// Leave out commits whose message matches any of these, e.g. `^Revert`.
+    pub message_excludes: RegexSet,
+    /// Merge the commits that share the ticket ID this finds in their
+    /// message into one changeset. The first capture group is the ID if the
+    /// regex has one, otherwise the whole match.
+    pub ticket_pattern: Option<Regex>,
+    /// Merge consecutive commits of the same author into one changeset while
+    /// each follows the previous one within this many seconds.
+    pub author_window: Option<i64>,
+    /// A file listing commits to leave out, like mass reformattings. Without
+    /// one the repo's `.git-blame-ignore-revs` is used, if there is one.
+    pub ignore_revs_file: Option<PathBuf>,
 }
 
-//Idea to intern filepaths it's prob shit
-struct InternedPath<'a> {
-    parent: Option<&'a InternedPath<'a>>,
-    element: String,
+/// Which commits of the history are turned into changesets.
+#[derive(Clone, Debug)]
+pub enum CommitSelection {
+    /// Every commit reachable from HEAD.
+    All,
+    /// Only commits with more than one parent.
+    Merges,
+    /// The commits on the first-parent chain of HEAD, merges included.
+    FirstParent,
+    /// Every commit with at most one parent.
+    NonMerges,
+    /// Merges whose message matches the regex, e.g. `^Merge pull request`.
+    PullRequests(Regex),
 }
 
+impl CommitSelection {
+    fn matches(&self, commit: &Commit) -> bool {
+        match self {
+            CommitSelection::All | CommitSelection::FirstParent => true,
+            CommitSelection::Merges => commit.parent_count() > 1,
+            CommitSelection::NonMerges => commit.parent_count() <= 1,
+            CommitSelection::PullRequests(pattern) => {
+                commit.parent_count() > 1
+                    && commit.message().is_some_and(|msg| pattern.is_match(msg))
+            }
+        }
+    }
+}
+
+/// Which signature of a commit an [`IdentityRule`] looks at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        })
+    }
+}
+
+/// Keeps or drops commits by a regex over `Name <email>` of their author or
+/// committer, after resolving aliases through the .mailmap.
+///
+/// A commit has to match one of the include rules of a role, if there are
+/// any, and none of the exclude rules.
+#[derive(Clone, Debug)]
+pub struct IdentityRule {
+    pub role: Role,
+    pub pattern: Regex,
+    pub exclude: bool,
+}
+
+impl IdentityRule {
+    /// Describes the rule that drops a commit with these identities, if any.
+    fn rejecting(rules: &[IdentityRule], author: &str, committer: &str) -> Option<String> {
+        let identity = |role| match role {
+            Role::Author => author,
+            Role::Committer => committer,
+        };
+        if let Some(rule) = rules
+            .iter()
+            .find(|rule| rule.exclude && rule.pattern.is_match(identity(rule.role)))
+        {
+            return Some(format!("{} ~ {}", rule.role, rule.pattern));
+        }
+        for role in [Role::Author, Role::Committer] {
+            let includes: Vec<&str> = rules
+                .iter()
+                .filter(|rule| !rule.exclude && rule.role == role)
+                .map(|rule| rule.pattern.as_str())
+                .collect();
+            let matched = rules.iter().any(|rule| {
+                !rule.exclude && rule.role == role && rule.pattern.is_match(identity(role))
+            });
+            if !includes.is_empty() && !matched {
+                return Some(format!("{} !~ {}", role, includes.join(" | ")));
+            }
+        }
+        None
+    }
+}
+
+/// What a merge commit is diffed against to get its changeset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MergeDiff {
+    /// Only the changes the merge brought into the first parent's history.
+    FirstParent,
+    /// Everything that changed on either side since the branches diverged.
+    MergeBase,
+}
+
+impl Default for GitFilter {
+    fn default() -> Self {
+        GitFilter {
+            start_date: None,
+            end_date: None,
+            revisions: Vec::new(),
+            max_commits: None,
+            path_filter: PathFilter::default(),
+            skip_ignored: true,
+            merge_diff: MergeDiff::FirstParent,
+            selection: CommitSelection::All,
+            identities: Vec::new(),
+            message_excludes: RegexSet::empty(),
+            ticket_pattern: None,
+            author_window: None,
+            ignore_revs_file: None,
+        }
+    }
+}
+
+/// The changesets of the selected commits, newest first, together with the
+/// renames seen along the way.
+pub struct History {
+    pub changesets: Vec<git_graph::ChangeSet>,
+    pub renames: git_graph::RenameMap,
+    pub excluded: Exclusions,
+    /// The selected commits, newest first.
+    pub commits: Vec<CommitInfo>,
+    /// The commits the walk started from.
+    pub tips: Vec<Oid>,
+}
+
+/// A selected commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub id: Oid,
+    /// Commit time in seconds since the epoch.
+    pub time: i64,
+    /// `Name <email>` of the author.
+    pub author: String,
+    pub summary: String,
+}
+
+/// How many commits each rule of the [`GitFilter`] left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Exclusions {
+    pub after_end: usize,
+    pub unselected: usize,
+    pub by_message: usize,
+    /// Commits listed in the ignore revs file.
+    pub ignored_revs: usize,
+    /// Commits dropped by the identity rules, keyed by the rule.
+    pub identities: BTreeMap<String, usize>,
+    /// The rule that ended the walk before the root commits, the commits
+    /// behind it are neither walked nor counted.
+    pub stopped: Option<Stop>,
+}
+
+/// Why the walk ended early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// It reached a commit before the start date.
+    StartDate,
+    /// It selected `max_commits` commits.
+    MaxCommits,
+}
+
+/// Walks the repo at `path` and turns the commits `filter` selects into
+/// changesets. The commits are diffed in parallel on rayon's current thread
+/// pool.
 pub fn repo_to_changesets(
     path: std::path::PathBuf,
     filter: &GitFilter,
-) -> Vec<git_graph::ChangeSet> {
-    let mut graph = Graph::<HashMap<String, String>, i64, Undirected>::new_undirected();
-
-    let repo = Repository::open(path).unwrap();
-    let commit_trees = search_repo(&repo, filter).unwrap();
-    let commit_trees: Vec<git2::Tree> = commit_trees.into_iter().collect();
-
-    let mut options = DiffOptions::new();
-    //no big impact
-    options.skip_binary_check(true);
-
-    let diffs = commit_trees.windows(2).flat_map(|window| match window {
-        [old, new] => repo
-            .diff_tree_to_tree(Some(old), Some(new), Some(&mut options))
-            .ok(),
-        _ => None,
-    });
-
-    let mut out: Vec<git_graph::ChangeSet> = Vec::new();
-    for d in diffs {
-        let change = d
-            .deltas()
-            .map(|delta| git_graph::GitFile {
-                name: delta
-                    .old_file()
-                    .path()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string(),
-                status: delta_status(&delta),
-            })
-            .filter(|file| {
-                filter.path_filters.is_empty() || filter.path_filters.is_match(&file.name)
+) -> Result<History, RorqualError> {
+    let history = walk_history(path, filter)?;
+    if history.commits.is_empty() {
+        return Err(RorqualError::EmptyHistory);
+    }
+    Ok(history)
+}
+
+/// Like [`repo_to_changesets`], but a walk that selects no commits isn't an
+/// error.
+pub(crate) fn walk_history(
+    path: std::path::PathBuf,
+    filter: &GitFilter,
+) -> Result<History, RorqualError> {
+    let repo = Repository::discover(&path).map_err(|source| RorqualError::NotARepo {
+        path: path.clone(),
+        source,
+    })?;
+    let mut excluded = Exclusions::default();
+    let (commits, tips) = search_repo(&repo, filter, &mut excluded)?;
+
+    let ignore_file = if filter.skip_ignored {
+        ignore_file(&repo)?
+    } else {
+        PathFilter::default()
+    };
+    // libgit2 repos can't be shared between threads, so the commits are split
+    // into one chunk per thread and every chunk is diffed on its own handle.
+    // The chunks are put back in order, so the number of threads doesn't
+    // change the result.
+    let ids: Vec<Oid> = commits.iter().map(Commit::id).collect();
+    let git_dir = repo.path();
+    let threads = rayon::current_num_threads();
+    let chunk_size = ids.len().div_ceil(threads).max(1);
+    let diffs: Vec<CommitDiff> = ids
+        .par_chunks(chunk_size)
+        .map(|chunk| {
+            let mut differ = Differ::open(git_dir, filter)?;
+            chunk.iter().map(|id| differ.diff(*id, filter)).collect()
+        })
+        .collect::<Result<Vec<Vec<CommitDiff>>, RorqualError>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let mut renames = git_graph::RenameMap::default();
+    let changesets = diffs
+        .into_iter()
+        .map(|diff| {
+            let changeset = git_graph::ChangeSet {
+                time: diff.time,
+                files: diff
+                    .changes
+                    .into_iter()
+                    .map(|change| change.resolve(&mut renames))
+                    .filter(|file| {
+                        filter.path_filter.matches(&file.name) && ignore_file.matches(&file.name)
+                    })
+                    .collect(),
+            };
+            (diff.keys, changeset)
+        })
+        .collect();
+    let changesets = match filter.author_window {
+        Some(window) => group_by_author(changesets, window),
+        None => changesets,
+    };
+    let changesets = group_by_ticket(changesets);
+
+    Ok(History {
+        changesets,
+        renames,
+        excluded,
+        commits: commits
+            .iter()
+            .map(|commit| CommitInfo {
+                id: commit.id(),
+                time: commit.time().seconds(),
+                author: commit.author().to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
             })
-            .collect();
-        out.push(change);
+            .collect(),
+        tips,
+    })
+}
+
+/// A repository handle and the caches to diff commits on one thread.
+struct Differ {
+    repo: Repository,
+    options: DiffOptions,
+    attributes: Attributes,
+    mailmap: Option<Mailmap>,
+}
+
+impl Differ {
+    fn open(path: &Path, filter: &GitFilter) -> Result<Self, RorqualError> {
+        let repo = Repository::open(path)?;
+        let mut options = DiffOptions::new();
+        //no big impact
+        options.skip_binary_check(true);
+        let mailmap = match filter.author_window {
+            Some(_) => Some(repo.mailmap()?),
+            None => None,
+        };
+        Ok(Differ {
+            repo,
+            options,
+            attributes: Attributes::default(),
+            mailmap,
+        })
+    }
+
+    fn diff(&mut self, id: Oid, filter: &GitFilter) -> Result<CommitDiff, RorqualError> {
+        let repo = &self.repo;
+        let commit = repo.find_commit(id)?;
+        let mut changes = diff_commit(repo, &commit, filter.merge_diff, &mut self.options)?;
+        if filter.skip_ignored {
+            let tree = commit.tree()?;
+            let attributes = &mut self.attributes;
+            changes.retain(|change| !attributes.is_ignored(repo, &tree, &change.file.name));
+        }
+        Ok(CommitDiff {
+            time: commit.time().seconds(),
+            keys: GroupKeys {
+                ticket: filter
+                    .ticket_pattern
+                    .as_ref()
+                    .and_then(|pattern| ticket(pattern, commit.message()?)),
+                author: self.mailmap.as_ref().and_then(|mailmap| {
+                    Some(commit.author_with_mailmap(mailmap).ok()?.to_string())
+                }),
+            },
+            changes,
+        })
+    }
+}
+
+/// The changes of a commit and what it is grouped by.
+struct CommitDiff {
+    time: i64,
+    keys: GroupKeys,
+    changes: Vec<Change>,
+}
+
+/// What decides whether changesets belong to the same logical change.
+struct GroupKeys {
+    ticket: Option<String>,
+    /// The author after resolving the .mailmap.
+    author: Option<String>,
+}
+
+fn ticket(pattern: &Regex, message: &str) -> Option<String> {
+    let captures = pattern.captures(message)?;
+    captures
+        .get(1)
+        .or_else(|| captures.get(0))
+        .map(|ticket| ticket.as_str().to_string())
+}
+
+/// Folds runs of changesets by the same author into the newest of the run, as
+/// long as each commit is at most `window` seconds older than the one before.
+fn group_by_author(
+    changesets: Vec<(GroupKeys, git_graph::ChangeSet)>,
+    window: i64,
+) -> Vec<(GroupKeys, git_graph::ChangeSet)> {
+    let mut grouped: Vec<(GroupKeys, git_graph::ChangeSet)> = Vec::new();
+    let mut previous_time = 0;
+    for (keys, changeset) in changesets {
+        let time = changeset.time;
+        match grouped.last_mut() {
+            Some((last_keys, last))
+                if keys.author.is_some()
+                    && keys.author == last_keys.author
+                    && (previous_time - time).abs() <= window =>
+            {
+                last_keys.ticket = last_keys.ticket.take().or(keys.ticket);
+                last.absorb(changeset);
+            }
+            _ => grouped.push((keys, changeset)),
+        }
+        previous_time = time;
+    }
+    grouped
+}
+
+/// Folds every changeset into the newest one with the same ticket.
+fn group_by_ticket(
+    changesets: Vec<(GroupKeys, git_graph::ChangeSet)>,
+) -> Vec<git_graph::ChangeSet> {
+    let mut grouped: Vec<git_graph::ChangeSet> = Vec::new();
+    let mut tickets: FxHashMap<String, usize> = FxHashMap::default();
+    for (keys, changeset) in changesets {
+        match keys.ticket {
+            Some(ticket) => match tickets.entry(ticket) {
+                Entry::Occupied(entry) => grouped[*entry.get()].absorb(changeset),
+                Entry::Vacant(entry) => {
+                    entry.insert(grouped.len());
+                    grouped.push(changeset);
+                }
+            },
+            None => grouped.push(changeset),
+        }
+    }
+    grouped
+}
+
+/// The `.rorqualignore` of the repo root.
+fn ignore_file(repo: &Repository) -> Result<PathFilter, RorqualError> {
+    match root_file(repo, IGNORE_FILE)? {
+        Some(content) => PathFilter::parse(&content),
+        None => Ok(PathFilter::default()),
+    }
+}
+
+/// The commits listed in `file`, or in the `.git-blame-ignore-revs` of the
+/// repo root. Comments start with `#`, revisions git doesn't know are skipped.
+fn ignored_revs(repo: &Repository, file: Option<&Path>) -> Result<FxHashSet<Oid>, RorqualError> {
+    let content = match file {
+        Some(file) => Some(fs::read_to_string(file)?),
+        None => root_file(repo, IGNORE_REVS_FILE)?,
+    };
+    Ok(content
+        .iter()
+        .flat_map(|content| content.lines())
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .flat_map(|rev| {
+            repo.revparse_single(rev)
+                .and_then(|obj| obj.peel_to_commit())
+        })
+        .map(|commit| commit.id())
+        .collect())
+}
+
+/// Reads a file from the root of the work tree, or of HEAD in a bare repo.
+fn root_file(repo: &Repository, name: &str) -> Result<Option<String>, RorqualError> {
+    match repo.workdir() {
+        Some(workdir) if workdir.join(name).exists() => {
+            Ok(Some(fs::read_to_string(workdir.join(name))?))
+        }
+        Some(_) => Ok(None),
+        None => {
+            let tree = repo.head()?.peel_to_tree()?;
+            match tree.get_path(Path::new(name)) {
+                Ok(entry) => {
+                    let blob = repo.find_blob(entry.id())?;
+                    Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+                }
+                Err(_) => Ok(None),
+            }
+        }
+    }
+}
+
+/// A file touched by a commit, as it was named at the time.
+struct Change {
+    file: git_graph::GitFile,
+    renamed_from: Option<String>,
+}
+
+impl Change {
+    /// Renames the file to its current path. Commits have to be resolved
+    /// newest first, so that a rename only applies to the history before it.
+    fn resolve(self, renames: &mut git_graph::RenameMap) -> git_graph::GitFile {
+        let Change {
+            mut file,
+            renamed_from,
+        } = self;
+        if let Some(current) = renames.get(&file.name) {
+            file.name = current.clone();
+        }
+        if let Some(old) = renamed_from {
+            renames.insert(old, file.name.clone());
+        }
+        file
     }
-    out
+}
+
+/// Diffs a commit against its parent. Root commits are diffed against the
+/// empty tree, merges against the first parent or the merge base.
+fn diff_commit(
+    repo: &Repository,
+    commit: &Commit,
+    merge_diff: MergeDiff,
+    options: &mut DiffOptions,
+) -> Result<Vec<Change>, git2::Error> {
+    let base = base_tree(repo, commit, merge_diff)?;
+    let tree = commit.tree()?;
+    let mut diff = repo.diff_tree_to_tree(base.as_ref(), Some(&tree), Some(options))?;
+    let mut find = DiffFindOptions::new();
+    find.renames(true).copies(true);
+    // Only an added file can be a copy, so the unmodified files it may have
+    // been copied from are only listed for commits that add one.
+    if diff.deltas().any(|delta| delta.status() == Delta::Added) {
+        options.include_unmodified(true);
+        let unmodified = repo.diff_tree_to_tree(base.as_ref(), Some(&tree), Some(options));
+        options.include_unmodified(false);
+        diff = unmodified?;
+        find.copies_from_unmodified(true);
+    }
+    diff.find_similar(Some(&mut find))?;
+
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() != Delta::Unmodified)
+        .map(|delta| Change {
+            file: git_graph::GitFile {
+                name: delta_path(&delta),
+                status: delta_status(&delta),
+            },
+            renamed_from: match delta.status() {
+                Delta::Renamed => Some(path_string(delta.old_file())),
+                _ => None,
+            },
+        })
+        .collect())
+}
+
+fn base_tree<'repo>(
+    repo: &'repo Repository,
+    commit: &Commit<'repo>,
+    merge_diff: MergeDiff,
+) -> Result<Option<Tree<'repo>>, git2::Error> {
+    let base = match (commit.parent_count(), merge_diff) {
+        (0, _) => return Ok(None),
+        (1, _) | (_, MergeDiff::FirstParent) => commit.parent(0)?,
+        (_, MergeDiff::MergeBase) => {
+            let base = repo.merge_base_many(&commit.parent_ids().collect::<Vec<_>>())?;
+            repo.find_commit(base)?
+        }
+    };
+    base.tree().map(Some)
+}
+
+fn delta_path(delta: &DiffDelta) -> String {
+    path_string(match delta.status() {
+        Delta::Deleted => delta.old_file(),
+        _ => delta.new_file(),
+    })
+}
+
+fn path_string(file: DiffFile) -> String {
+    file.path().unwrap().to_string_lossy().to_string()
 }
 
 fn delta_status(delta: &DiffDelta) -> git_graph::Status {
     match delta.status() {
-        Delta::Added | Delta::Copied => git_graph::Status::Added,
+        Delta::Added => git_graph::Status::Added,
+        Delta::Copied => git_graph::Status::Copied,
         Delta::Deleted | Delta::Ignored => git_graph::Status::Deleted,
         _ => git_graph::Status::Modified,
     }
 }
 
+/// Whether every one of `old_tips` is still reachable from the commits a walk
+/// over `revisions` starts from, so that no history before them was
+/// rewritten.
+pub(crate) fn extends(
+    path: &Path,
+    revisions: &[String],
+    old_tips: &[Oid],
+) -> Result<bool, RorqualError> {
+    let repo = Repository::discover(path).map_err(|source| RorqualError::NotARepo {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let tips = start_walk(&repo, &mut repo.revwalk()?, revisions)?;
+    for old in old_tips {
+        let mut reachable = false;
+        for tip in &tips {
+            if tip == old || repo.graph_descendant_of(*tip, *old).unwrap_or(false) {
+                reachable = true;
+                break;
+            }
+        }
+        if !reachable {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Pushes the revisions, or HEAD if they only hide commits, and returns the
+/// commits the walk starts from.
+fn start_walk(
+    repo: &Repository,
+    rev_walk: &mut Revwalk,
+    revisions: &[String],
+) -> Result<Vec<Oid>, RorqualError> {
+    let mut tips = Vec::new();
+    for spec in revisions {
+        tips.extend(push_revisions(repo, rev_walk, spec)?);
+    }
+    if revisions.iter().all(|spec| spec.starts_with('^')) {
+        let head = repo.head().map_err(|e| match e.code() {
+            ErrorCode::UnbornBranch => RorqualError::EmptyHistory,
+            _ => RorqualError::Git(e),
+        })?;
+        let head = head.peel_to_commit()?.id();
+        rev_walk.push(head)?;
+        tips.push(head);
+    }
+    tips.sort();
+    tips.dedup();
+    Ok(tips)
+}
+
 fn search_repo<'repo>(
     repo: &'repo Repository,
     filter: &GitFilter,
-) -> Result<impl Iterator<Item = git2::Tree<'repo>> + 'repo, git2::Error> {
+    excluded: &mut Exclusions,
+) -> Result<(Vec<Commit<'repo>>, Vec<Oid>), RorqualError> {
     let mut rev_walk = repo.revwalk()?;
-    rev_walk.set_sorting(Sort::NONE);
-    rev_walk.push_head()?;
+    rev_walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    let tips = start_walk(repo, &mut rev_walk, &filter.revisions)?;
+    if let CommitSelection::FirstParent = filter.selection {
+        rev_walk.simplify_first_parent()?;
+    }
+
+    let dt = filter.start_date.map(|date| date.timestamp());
+    let end = filter.end_date.map(|date| date.timestamp());
+    let mailmap = if filter.identities.is_empty() {
+        None
+    } else {
+        Some(repo.mailmap()?)
+    };
+    let ignored_revs = ignored_revs(repo, filter.ignore_revs_file.as_deref())?;
+    let max_commits = filter.max_commits.map_or(usize::MAX, |max| max as usize);
 
-    let dt = filter.start_date.unwrap().naive_utc().timestamp();
+    let mut commits = Vec::new();
+    for commit_id in rev_walk.by_ref() {
+        let commit = repo.find_commit(commit_id?)?;
+        if dt.is_some_and(|dt| commit.time().seconds() <= dt) {
+            excluded.stopped = Some(Stop::StartDate);
+            break;
+        }
+        if commits.len() == max_commits {
+            excluded.stopped = Some(Stop::MaxCommits);
+            break;
+        }
+        if end.is_some_and(|end| commit.time().seconds() > end) {
+            excluded.after_end += 1;
+        } else if ignored_revs.contains(&commit.id()) {
+            excluded.ignored_revs += 1;
+        } else if !filter.selection.matches(&commit) {
+            excluded.unselected += 1;
+        } else if commit
+            .message()
+            .is_some_and(|msg| filter.message_excludes.is_match(msg))
+        {
+            excluded.by_message += 1;
+        } else if let Some(rule) = mailmap
+            .as_ref()
+            .and_then(|mailmap| rejecting_rule(&commit, mailmap, &filter.identities))
+        {
+            *excluded.identities.entry(rule).or_insert(0) += 1;
+        } else {
+            commits.push(commit);
+        }
+    }
+
+    Ok((commits, tips))
+}
+
+fn rejecting_rule(commit: &Commit, mailmap: &Mailmap, rules: &[IdentityRule]) -> Option<String> {
+    let identity = |signature: Result<Signature, git2::Error>| {
+        signature
+            .map(|signature| signature.to_string())
+            .unwrap_or_default()
+    };
+    IdentityRule::rejecting(
+        rules,
+        &identity(commit.author_with_mailmap(mailmap)),
+        &identity(commit.committer_with_mailmap(mailmap)),
+    )
+}
+
+/// Pushes or, with a leading `^`, hides the commits of a revspec or ref glob.
+/// Tags and other refs are peeled to the commit they point at. Returns the
+/// commits that were pushed.
+fn push_revisions(
+    repo: &Repository,
+    rev_walk: &mut Revwalk,
+    spec: &str,
+) -> Result<Vec<Oid>, RorqualError> {
+    let unknown = |source| RorqualError::UnknownRevision {
+        spec: spec.to_string(),
+        source,
+    };
+    let is_glob = spec.contains(&['*', '?', '['][..]);
+    if let Some(hidden) = spec.strip_prefix('^') {
+        if is_glob {
+            for commit in glob_commits(repo, hidden).map_err(unknown)? {
+                rev_walk.hide(commit)?;
+            }
+        } else {
+            let object = repo.revparse_single(hidden).map_err(unknown)?;
+            rev_walk.hide(object.peel_to_commit().map_err(unknown)?.id())?;
+        }
+        return Ok(Vec::new());
+    }
+    if is_glob {
+        let tips = glob_commits(repo, spec).map_err(unknown)?;
+        for tip in &tips {
+            rev_walk.push(*tip)?;
+        }
+        return Ok(tips);
+    }
+
+    let revspec = repo.revparse(spec).map_err(unknown)?;
+    let commit = |object: Option<&Object>| -> Result<Oid, RorqualError> {
+        match object {
+            Some(object) => Ok(object.peel_to_commit().map_err(unknown)?.id()),
+            None => Ok(repo.head()?.peel_to_commit()?.id()),
+        }
+    };
+
+    let from = commit(revspec.from())?;
+    if revspec.mode().contains(RevparseMode::SINGLE) {
+        rev_walk.push(from)?;
+        Ok(vec![from])
+    } else if revspec.mode().contains(RevparseMode::MERGE_BASE) {
+        let to = commit(revspec.to())?;
+        rev_walk.push(from)?;
+        rev_walk.push(to)?;
+        rev_walk.hide(repo.merge_base(from, to)?)?;
+        Ok(vec![from, to])
+    } else {
+        let to = commit(revspec.to())?;
+        rev_walk.push(to)?;
+        rev_walk.hide(from)?;
+        Ok(vec![to])
+    }
+}
+
+/// The commits the refs matching `glob` point at, an error if there are none.
+fn glob_commits(repo: &Repository, glob: &str) -> Result<Vec<Oid>, git2::Error> {
+    // As git, globs that don't start with refs/ are taken to be below it.
+    let glob = if glob.starts_with("refs/") {
+        glob.to_string()
+    } else {
+        format!("refs/{}", glob)
+    };
+    let mut commits = Vec::new();
+    for reference in repo.references_glob(&glob)? {
+        if let Ok(commit) = reference?.peel_to_commit() {
+            commits.push(commit.id());
+        }
+    }
+    if commits.is_empty() {
+        return Err(git2::Error::from_str("matches no refs"));
+    }
+    Ok(commits)
+}
+
+/// Reads a date as `2021-01-31`, as an RFC 3339 time or relative to `now`
+/// like `6.months.ago` or `90d`.
+pub fn parse_date(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, RorqualError> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("a valid time")));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    relative_date(input, now).ok_or_else(|| RorqualError::BadDate {
+        input: input.to_string(),
+        reason: "expected YYYY-MM-DD, an RFC 3339 time, 6.months.ago or 90d".to_string(),
+    })
+}
+
+/// Reads a date like [`parse_date`], but a date without a time means the end
+/// of that day, so the day is included when the date ends a range.
+pub fn parse_end_date(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, RorqualError> {
+    match NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        Ok(date) => Ok(Utc.from_utc_datetime(&date.and_hms_opt(23, 59, 59).expect("a valid time"))),
+        Err(_) => parse_date(input, now),
+    }
+}
+
+fn relative_date(input: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    // Either `6.months.ago` or `90d`, with a whole number of units.
+    let input = input.strip_suffix(".ago").unwrap_or(input);
+    let (amount, unit) = match input.split_once('.') {
+        Some(parts) => parts,
+        None => input.split_at(input.find(|c: char| !c.is_ascii_digit())?),
+    };
+    if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: i64 = amount.parse().ok()?;
+    let unit = if unit.len() > 1 {
+        unit.strip_suffix('s').unwrap_or(unit)
+    } else {
+        unit
+    };
+    let seconds = match unit {
+        "s" | "sec" | "second" => 1,
+        "min" | "minute" => 60,
+        "h" | "hour" => 60 * 60,
+        "d" | "day" => 24 * 60 * 60,
+        "w" | "week" => 7 * 24 * 60 * 60,
+        "m" | "month" => return months_before(now, amount),
+        "y" | "year" => return months_before(now, amount.checked_mul(12)?),
+        _ => return None,
+    };
+    // Durations are milliseconds, so this can't be out of range for them.
+    let millis = amount.checked_mul(seconds)?.checked_mul(1000)?;
+    now.checked_sub_signed(Duration::milliseconds(millis))
+}
 
-    let commit_trees = rev_walk
-        .flat_map(move |commit_id| repo.find_commit(commit_id.unwrap()))
-        .take_while(move |commit| commit.time().seconds() > dt)
-        .filter(|commit| commit.parents().len() != 1)
-        // .filter(|commit| commit.message().and_then(|msg: &str| Some(msg.contains("Merge pull request"))).unwrap_or(false))
-        .flat_map(|commit| commit.tree());
-    Ok(commit_trees)
+/// Steps back whole calendar months, days past the end of the target month
+/// are clamped to its last day.
+fn months_before(now: DateTime<Utc>, months: i64) -> Option<DateTime<Utc>> {
+    let month = i64::from(now.year()) * 12 + i64::from(now.month0()) - months;
+    let year = i32::try_from(month.div_euclid(12)).ok()?;
+    let month = month.rem_euclid(12) as u32 + 1;
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let last_day = (1..=31)
+        .rev()
+        .find(|day| first.with_day(*day).is_some())
+        .unwrap_or(1);
+    let date = first.with_day(now.day().min(last_day))?;
+    Some(Utc.from_utc_datetime(&date.and_time(now.time())))
 }
 
+#[cfg(test)]
 use std::fs::{copy, create_dir, rename};
-use std::path::Path;
+#[cfg(test)]
 use tempfile::TempDir;
+#[cfg(test)]
 use walkdir::WalkDir;
 
+use super::attributes::Attributes;
+use super::error::RorqualError;
 use super::git_graph;
+use super::path_filter::PathFilter;
 
+#[cfg(test)]
 static FIXTURES_PATH: &str = "./tests/fixtures";
 
-fn load_fixture_repo(name: &str) -> Result<(TempDir, Repository), Box<dyn std::error::Error>> {
+#[cfg(test)]
+pub(crate) fn load_fixture_repo(
+    name: &str,
+) -> Result<(TempDir, Repository), Box<dyn std::error::Error>> {
     let tmpdir = TempDir::new()?;
     let fixture_path = Path::new(FIXTURES_PATH).join(name);
     copy_recursively(&fixture_path, tmpdir.path())?;
@@ -122,6 +884,7 @@ fn load_fixture_repo(name: &str) -> Result<(TempDir, Repository), Box<dyn std::e
     Ok((tmpdir, repo))
 }
 
+#[cfg(test)]
 fn copy_recursively(source: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
     for source_entry in WalkDir::new(source) {
         let entry = source_entry?;
@@ -141,9 +904,675 @@ fn copy_recursively(source: &Path, target: &Path) -> Result<(), Box<dyn std::err
     Ok(())
 }
 
+#[cfg(test)]
+fn file_names(changes: &[git_graph::ChangeSet]) -> Vec<Vec<&str>> {
+    changes
+        .iter()
+        .map(|change| {
+            let mut names: Vec<&str> = change.files.iter().map(|file| file.name.as_str()).collect();
+            names.sort_unstable();
+            names
+        })
+        .collect()
+}
+
 #[test]
 fn test_scan_repo() {
     let (_dir, repo) = load_fixture_repo("basic-repo").unwrap();
-    let trees = search_repo(&repo).unwrap();
-    assert_eq!(2, trees.count());
+    let (commits, _) =
+        search_repo(&repo, &GitFilter::default(), &mut Exclusions::default()).unwrap();
+    assert_eq!(2, commits.len());
+}
+
+#[test]
+fn test_diff_commit_against_parent() {
+    let (dir, _repo) = load_fixture_repo("basic-repo").unwrap();
+    let history = repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default()).unwrap();
+
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![
+            vec![
+                "copy_of_readme",
+                "modified",
+                "new_file",
+                "renamed_new",
+                "renamed_old"
+            ],
+            vec!["Readme", "modified", "renamed_new", "renamed_old"],
+        ]
+    );
+    let times: Vec<i64> = history.changesets.iter().map(|set| set.time).collect();
+    assert_eq!(times, vec![1597592558, 1597592432]);
+}
+
+#[test]
+fn test_renames_fold_into_current_path() {
+    let (dir, _repo) = load_fixture_repo("basic-repo").unwrap();
+    let history = repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default()).unwrap();
+
+    assert_eq!(history.renames.len(), 1);
+    assert_eq!(history.renames["deleted_old"], "renamed_new");
+
+    let copy = history.changesets[0]
+        .files
+        .iter()
+        .find(|file| file.name == "copy_of_readme")
+        .unwrap();
+    assert!(matches!(copy.status, git_graph::Status::Copied));
+}
+
+#[cfg(test)]
+fn selected_summaries(selection: CommitSelection) -> Vec<String> {
+    walked_summaries(&GitFilter {
+        selection,
+        ..GitFilter::default()
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+fn walked_summaries(filter: &GitFilter) -> Result<Vec<String>, RorqualError> {
+    let (_dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let (commits, _) = search_repo(&repo, filter, &mut Exclusions::default())?;
+    Ok(commits
+        .iter()
+        .map(|commit| commit.summary().unwrap().to_string())
+        .collect())
+}
+
+#[test]
+fn test_merge_diff_first_parent() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        selection: CommitSelection::Merges,
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![vec!["c", "e"], vec!["a", "d"]]
+    );
+}
+
+#[test]
+fn test_merge_diff_merge_base() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        merge_diff: MergeDiff::MergeBase,
+        selection: CommitSelection::Merges,
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![vec!["a", "c", "e"], vec!["a", "b", "d"]]
+    );
+}
+
+#[test]
+fn test_select_all_commits() {
+    assert_eq!(
+        selected_summaries(CommitSelection::All),
+        vec![
+            "Merge pull request #2 from someone/fix",
+            "edit a",
+            "fix e",
+            "fix c",
+            "squashed change (#1)",
+            "Merge branch 'feature'",
+            "edit b",
+            "feature a",
+            "init",
+        ]
+    );
+}
+
+#[test]
+fn test_select_merges() {
+    assert_eq!(
+        selected_summaries(CommitSelection::Merges),
+        vec![
+            "Merge pull request #2 from someone/fix",
+            "Merge branch 'feature'",
+        ]
+    );
+}
+
+#[test]
+fn test_select_first_parent() {
+    assert_eq!(
+        selected_summaries(CommitSelection::FirstParent),
+        vec![
+            "Merge pull request #2 from someone/fix",
+            "edit a",
+            "squashed change (#1)",
+            "Merge branch 'feature'",
+            "edit b",
+            "init",
+        ]
+    );
+}
+
+#[test]
+fn test_select_non_merges() {
+    assert_eq!(
+        selected_summaries(CommitSelection::NonMerges),
+        vec![
+            "edit a",
+            "fix e",
+            "fix c",
+            "squashed change (#1)",
+            "edit b",
+            "feature a",
+            "init",
+        ]
+    );
+}
+
+#[test]
+fn test_select_pull_requests() {
+    let pattern = Regex::new("^Merge pull request").unwrap();
+    assert_eq!(
+        selected_summaries(CommitSelection::PullRequests(pattern)),
+        vec!["Merge pull request #2 from someone/fix"]
+    );
+}
+
+#[test]
+fn test_max_commits_stops_walk() {
+    let (_dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        selection: CommitSelection::NonMerges,
+        max_commits: Some(3),
+        ..GitFilter::default()
+    };
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &filter, &mut excluded).unwrap();
+
+    let summaries: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+    assert_eq!(summaries, vec!["edit a", "fix e", "fix c"]);
+    assert_eq!(
+        excluded,
+        Exclusions {
+            unselected: 1,
+            stopped: Some(Stop::MaxCommits),
+            ..Exclusions::default()
+        }
+    );
+}
+
+#[test]
+fn test_start_date_exclusions() {
+    let (_dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        start_date: Some(Utc.with_ymd_and_hms(2021, 1, 5, 0, 0, 0).unwrap()),
+        selection: CommitSelection::Merges,
+        ..GitFilter::default()
+    };
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &filter, &mut excluded).unwrap();
+
+    assert_eq!(commits.len(), 1);
+    assert_eq!(
+        excluded,
+        Exclusions {
+            unselected: 4,
+            stopped: Some(Stop::StartDate),
+            ..Exclusions::default()
+        }
+    );
+}
+
+#[test]
+fn test_discovers_repo_from_subdirectory() {
+    let (dir, _repo) = load_fixture_repo("basic-repo").unwrap();
+    let subdir = dir.path().join("nested");
+    std::fs::create_dir(&subdir).unwrap();
+    let history = repo_to_changesets(subdir, &GitFilter::default()).unwrap();
+    assert_eq!(history.changesets.len(), 2);
+}
+
+#[test]
+fn test_not_a_repo() {
+    let dir = TempDir::new().unwrap();
+    let result = repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default());
+    assert!(matches!(result, Err(RorqualError::NotARepo { .. })));
+}
+
+#[test]
+fn test_empty_history() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        start_date: Some(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap()),
+        ..GitFilter::default()
+    };
+    let result = repo_to_changesets(dir.path().to_path_buf(), &filter);
+    assert!(matches!(result, Err(RorqualError::EmptyHistory)));
+}
+
+#[test]
+fn test_end_date_exclusions() {
+    let (_dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let filter = GitFilter {
+        start_date: Some(Utc.with_ymd_and_hms(2021, 1, 2, 0, 0, 0).unwrap()),
+        end_date: Some(Utc.with_ymd_and_hms(2021, 1, 6, 0, 0, 0).unwrap()),
+        ..GitFilter::default()
+    };
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &filter, &mut excluded).unwrap();
+    let summaries: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+
+    assert_eq!(
+        summaries,
+        [
+            "squashed change (#1)",
+            "Merge branch 'feature'",
+            "edit b",
+            "feature a"
+        ]
+    );
+    assert_eq!(excluded.after_end, 4);
+    assert_eq!(excluded.stopped, Some(Stop::StartDate));
+}
+
+#[test]
+fn test_revision_ranges() {
+    let walk = |spec: &str| {
+        walked_summaries(&GitFilter {
+            revisions: vec![spec.to_string()],
+            ..GitFilter::default()
+        })
+    };
+
+    assert_eq!(walk("v1.0..v1.1").unwrap(), ["squashed change (#1)"]);
+    assert_eq!(
+        walk("v1.1..").unwrap(),
+        [
+            "Merge pull request #2 from someone/fix",
+            "edit a",
+            "fix e",
+            "fix c"
+        ]
+    );
+    assert_eq!(
+        walk("HEAD^1...HEAD^2").unwrap(),
+        ["edit a", "fix e", "fix c"]
+    );
+    assert_eq!(walk("v1.0").unwrap().len(), 4);
+    assert!(matches!(
+        walk("v2.0..v1.0"),
+        Err(RorqualError::UnknownRevision { .. })
+    ));
+}
+
+#[test]
+fn test_parse_date() {
+    let now = Utc.with_ymd_and_hms(2021, 8, 31, 12, 0, 0).unwrap();
+    let midnight = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+    let noon = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap();
+
+    assert_eq!(
+        parse_date("2021-01-31", now).unwrap(),
+        midnight(2021, 1, 31)
+    );
+    assert_eq!(
+        parse_date("2021-01-31T10:00:00+02:00", now).unwrap(),
+        Utc.with_ymd_and_hms(2021, 1, 31, 8, 0, 0).unwrap()
+    );
+    assert_eq!(parse_date("6.months.ago", now).unwrap(), noon(2021, 2, 28));
+    assert_eq!(parse_date("1.month.ago", now).unwrap(), noon(2021, 7, 31));
+    assert_eq!(parse_date("2.weeks.ago", now).unwrap(), noon(2021, 8, 17));
+    assert_eq!(parse_date("90d", now).unwrap(), noon(2021, 6, 2));
+    assert_eq!(parse_date("1y", now).unwrap(), noon(2020, 8, 31));
+    assert_eq!(parse_date("9m", now).unwrap(), noon(2020, 11, 30));
+    assert!(matches!(
+        parse_date("soon", now),
+        Err(RorqualError::BadDate { .. })
+    ));
+    assert!(parse_date("3.fortnights.ago", now).is_err());
+    for malformed in &[
+        "1.5d",
+        "1.5.days.ago",
+        "d",
+        ".days.ago",
+        "+3d",
+        "2.weeks.ago.ago",
+    ] {
+        assert!(matches!(
+            parse_date(malformed, now),
+            Err(RorqualError::BadDate { .. })
+        ));
+    }
+    for huge in &["999999999d", "9223372036854775807s", "999999999999999999y"] {
+        assert!(matches!(
+            parse_date(huge, now),
+            Err(RorqualError::BadDate { .. })
+        ));
+    }
+
+    assert_eq!(
+        parse_end_date("2021-01-31", now).unwrap(),
+        Utc.with_ymd_and_hms(2021, 1, 31, 23, 59, 59).unwrap()
+    );
+    assert_eq!(parse_end_date("90d", now).unwrap(), noon(2021, 6, 2));
+}
+
+#[test]
+fn test_refs_and_globs() {
+    let walk = |specs: &[&str]| {
+        walked_summaries(&GitFilter {
+            revisions: specs.iter().map(|spec| spec.to_string()).collect(),
+            ..GitFilter::default()
+        })
+        .unwrap()
+    };
+
+    assert_eq!(walk(&["refs/heads/release/*"]).len(), 5);
+    assert_eq!(walk(&["fix", "^release/1.0"]), ["fix e", "fix c"]);
+    assert_eq!(walk(&["refs/heads/*", "^refs/heads/release/*"]).len(), 4);
+    assert_eq!(
+        walk(&["^fix"]),
+        ["Merge pull request #2 from someone/fix", "edit a"]
+    );
+    assert_eq!(walk(&["fix", "v1.0..v1.1"]).len(), 3);
+    for spec in &["refs/heads/nosuch/*", "^refs/heads/nosuch/*"] {
+        assert!(matches!(
+            walked_summaries(&GitFilter {
+                revisions: vec![spec.to_string()],
+                ..GitFilter::default()
+            }),
+            Err(RorqualError::UnknownRevision { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_bare_repo() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    repo.config().unwrap().set_bool("core.bare", true).unwrap();
+    let filter = GitFilter {
+        revisions: vec!["fix".to_string()],
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().join(".git"), &filter).unwrap();
+
+    assert!(Repository::open(dir.path().join(".git")).unwrap().is_bare());
+    assert_eq!(history.changesets.len(), 7);
+}
+
+#[test]
+fn test_skip_ignored_files() {
+    let (dir, _repo) = load_fixture_repo("attributes-repo").unwrap();
+    let history = repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default()).unwrap();
+
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![
+            vec![".gitattributes", "src/a.rs", "vendor/lib.js"],
+            vec!["src/a.rs", "src/b.rs"],
+            vec![".gitattributes", ".rorqualignore", "src/a.rs", "src/b.rs"],
+        ]
+    );
+}
+
+#[test]
+fn test_keep_ignored_files() {
+    let (dir, _repo) = load_fixture_repo("attributes-repo").unwrap();
+    let filter = GitFilter {
+        skip_ignored: false,
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+
+    assert_eq!(history.changesets[1].files.len(), 7);
+}
+
+#[cfg(test)]
+fn identity_rule(role: Role, pattern: &str, exclude: bool) -> IdentityRule {
+    IdentityRule {
+        role,
+        pattern: Regex::new(pattern).unwrap(),
+        exclude,
+    }
+}
+
+#[cfg(test)]
+fn summaries_by_identity(identities: Vec<IdentityRule>) -> (Vec<String>, Exclusions) {
+    let (_dir, repo) = load_fixture_repo("authors-repo").unwrap();
+    let filter = GitFilter {
+        identities,
+        ..GitFilter::default()
+    };
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &filter, &mut excluded).unwrap();
+    let summaries = commits
+        .iter()
+        .map(|commit| commit.summary().unwrap().to_string())
+        .collect();
+    (summaries, excluded)
+}
+
+#[test]
+fn test_exclude_bots() {
+    let (summaries, excluded) = summaries_by_identity(vec![
+        identity_rule(Role::Author, r"^dependabot\[bot\]", true),
+        identity_rule(Role::Author, r"renovate", true),
+    ]);
+
+    assert_eq!(summaries, ["edit b", "edit a", "init"]);
+    assert_eq!(excluded.identities[r"author ~ ^dependabot\[bot\]"], 1);
+    assert_eq!(excluded.identities["author ~ renovate"], 1);
+}
+
+#[test]
+fn test_include_authors_through_mailmap() {
+    let (summaries, excluded) = summaries_by_identity(vec![
+        identity_rule(Role::Author, "^Jane Roe <jane@roe.org>$", false),
+        identity_rule(Role::Committer, "^John Doe", true),
+    ]);
+
+    // "edit a" was written as J. Roe <jroe@old-company.com>
+    assert_eq!(summaries, ["edit b"]);
+    assert_eq!(excluded.identities["committer ~ ^John Doe"], 2);
+    assert_eq!(
+        excluded.identities["author !~ ^Jane Roe <jane@roe.org>$"],
+        2
+    );
+
+    let (summaries, _) =
+        summaries_by_identity(vec![identity_rule(Role::Author, "^Jane Roe", false)]);
+    assert_eq!(summaries, ["edit b", "edit a"]);
+}
+
+#[test]
+fn test_committer_rules() {
+    let (summaries, excluded) = summaries_by_identity(vec![
+        identity_rule(Role::Committer, "noreply@github.com", true),
+        identity_rule(Role::Committer, "Doe", false),
+        identity_rule(Role::Committer, "Roe", false),
+    ]);
+
+    assert_eq!(summaries, ["edit b", "edit a", "init"]);
+    assert_eq!(excluded.identities["committer ~ noreply@github.com"], 1);
+    assert_eq!(excluded.identities["committer !~ Doe | Roe"], 1);
+}
+
+#[test]
+fn test_exclude_messages() {
+    let (_dir, repo) = load_fixture_repo("tickets-repo").unwrap();
+    let filter = GitFilter {
+        message_excludes: RegexSet::new(["^Revert", "cs-fix", r"\[skip-coupling\]"]).unwrap(),
+        ..GitFilter::default()
+    };
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &filter, &mut excluded).unwrap();
+    let summaries: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+
+    assert_eq!(
+        summaries,
+        [
+            "NEXT-1: wire up frontend",
+            "NEXT-2 unrelated",
+            "NEXT-1: add api",
+            "init"
+        ]
+    );
+    assert_eq!(excluded.by_message, 3);
+}
+
+#[test]
+fn test_group_by_ticket() {
+    let (dir, _repo) = load_fixture_repo("tickets-repo").unwrap();
+    let filter = GitFilter {
+        message_excludes: RegexSet::new(["^Revert", "cs-fix", r"\[skip-coupling\]"]).unwrap(),
+        ticket_pattern: Some(Regex::new(r"^(NEXT-\d+):?").unwrap()),
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![vec!["a", "b", "d"], vec!["c"], vec!["a", "b", "c", "d"]]
+    );
+    let times: Vec<i64> = history.changesets.iter().map(|set| set.time).collect();
+    assert_eq!(
+        times,
+        [
+            Utc.with_ymd_and_hms(2021, 3, 5, 10, 0, 0)
+                .unwrap()
+                .timestamp(),
+            Utc.with_ymd_and_hms(2021, 3, 4, 10, 0, 0)
+                .unwrap()
+                .timestamp(),
+            Utc.with_ymd_and_hms(2021, 3, 1, 10, 0, 0)
+                .unwrap()
+                .timestamp(),
+        ]
+    );
+}
+
+#[test]
+fn test_group_by_author_window() {
+    let (dir, _repo) = load_fixture_repo("sessions-repo").unwrap();
+    let history = repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default()).unwrap();
+    assert_eq!(history.changesets.len(), 7);
+
+    let filter = GitFilter {
+        author_window: Some(30 * 60),
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+
+    // Jane Roe's commits are grouped with the ones of her alias J. Roe
+    assert_eq!(
+        file_names(&history.changesets),
+        vec![
+            vec!["a"],
+            vec!["a", "b"],
+            vec!["d"],
+            vec!["a", "b", "c"],
+            vec![".mailmap"]
+        ]
+    );
+    assert_eq!(
+        history.changesets[1].time,
+        Utc.with_ymd_and_hms(2021, 4, 1, 11, 5, 0)
+            .unwrap()
+            .timestamp()
+    );
+
+    // Commits exactly the window apart are still grouped.
+    let filter = GitFilter {
+        author_window: Some(10 * 60),
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+    assert_eq!(history.changesets.len(), 5);
+    let filter = GitFilter {
+        author_window: Some(10 * 60 - 1),
+        ..GitFilter::default()
+    };
+    let history = repo_to_changesets(dir.path().to_path_buf(), &filter).unwrap();
+    assert_eq!(history.changesets.len(), 7);
+}
+
+#[test]
+fn test_ignore_revs() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    std::fs::write(
+        dir.path().join(".git-blame-ignore-revs"),
+        "# reformat\n30aaf15edeb3f429901367d7e52165915760f813\n\n\
+         98e5bfa2bda40404658911f73627635908f26bd8 # fix c\n\
+         0000000000000000000000000000000000000000\n",
+    )
+    .unwrap();
+    let mut excluded = Exclusions::default();
+    let (commits, _) = search_repo(&repo, &GitFilter::default(), &mut excluded).unwrap();
+    let summaries: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+
+    assert_eq!(commits.len(), 7);
+    assert!(!summaries.contains(&"edit a"));
+    assert!(!summaries.contains(&"fix c"));
+    assert_eq!(excluded.ignored_revs, 2);
+
+    let other = dir.path().join("reformats");
+    std::fs::write(&other, "2cb94c7\n").unwrap();
+    let filter = GitFilter {
+        ignore_revs_file: Some(other),
+        ..GitFilter::default()
+    };
+    let (commits, _) = search_repo(&repo, &filter, &mut Exclusions::default()).unwrap();
+    let summaries: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+
+    assert_eq!(commits.len(), 8);
+    assert!(!summaries.contains(&"fix e"));
+}
+
+#[test]
+fn test_walk_tips() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let tips = |specs: &[&str]| {
+        let revisions: Vec<String> = specs.iter().map(|spec| spec.to_string()).collect();
+        let tips = start_walk(&repo, &mut repo.revwalk().unwrap(), &revisions).unwrap();
+        tips.iter()
+            .map(|tip| tip.to_string()[..7].to_string())
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(tips(&[]), ["54b72ee"]);
+    assert_eq!(tips(&["v1.0..v1.1", "^fix"]), ["8a96612"]);
+    assert_eq!(tips(&["heads/*"]), ["2cb94c7", "54b72ee", "8a96612"]);
+
+    let old_tips = [Oid::from_str("2cb94c73b24d6edca7f0489ea2803c690bd609b6").unwrap()];
+    assert!(extends(dir.path(), &[], &old_tips).unwrap());
+    assert!(!extends(dir.path(), &["release/1.0".to_string()], &old_tips).unwrap());
+}
+
+#[test]
+fn test_diffs_independent_of_threads() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let changesets = |threads| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let history = pool
+            .install(|| repo_to_changesets(dir.path().to_path_buf(), &GitFilter::default()))
+            .unwrap();
+        history
+            .changesets
+            .iter()
+            .map(|set| {
+                let names = set.files.iter().map(|file| file.name.clone()).collect();
+                (set.time, names)
+            })
+            .collect::<Vec<(i64, Vec<String>)>>()
+    };
+
+    let single = changesets(1);
+    assert_eq!(single.len(), 9);
+    assert_eq!(changesets(4), single);
 }
@@ -1,65 +1,291 @@
-// TODO Only for test purposes REMOVE ME
-#![allow(dead_code)]
+use num_traits::Zero;
+use ordered_float::OrderedFloat;
 use rustc_hash::FxHashMap;
+use std::fmt;
+use std::ops::Add;
 
 use petgraph::{
-    data::Build,
-    graph::NodeIndex,
-    visit::{EdgeRef, IntoEdgeReferences},
+    graph::{EdgeIndex, NodeIndex},
+    visit::EdgeRef,
     Graph, Undirected,
 };
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use super::error::RorqualError;
 #[derive(Copy, Clone)]
 pub enum Status {
     Added,
+    /// Added as a copy of another file.
+    Copied,
     Deleted,
     Modified,
 }
 
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Added => "added",
+            Status::Copied => "copied",
+            Status::Deleted => "deleted",
+            Status::Modified => "modified",
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct GitFile {
     pub status: Status,
     pub name: String,
 }
 
+/// A file in the graph and how many changesets it was part of.
+#[derive(Clone)]
+pub struct FileNode {
+    /// Status of the newest change.
+    pub status: Status,
+    pub name: String,
+    pub commits: u32,
+    /// Commit times of the oldest and newest change, in seconds since the epoch.
+    pub first_change: i64,
+    pub last_change: i64,
+}
+
+impl FileNode {
+    fn new(file: GitFile, time: i64) -> Self {
+        FileNode {
+            status: file.status,
+            name: file.name,
+            commits: 1,
+            first_change: time,
+            last_change: time,
+        }
+    }
+
+    fn absorb(&mut self, other: FileNode) {
+        if other.last_change > self.last_change {
+            self.status = other.status;
+        }
+        self.commits += other.commits;
+        self.first_change = self.first_change.min(other.first_change);
+        self.last_change = self.last_change.max(other.last_change);
+    }
+}
+
+/// How often two files were changed together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoChange {
+    /// Number of changesets that contained both files.
+    pub count: u32,
+    /// Sum of what each of those changesets contributed, see [`Oversized`].
+    pub weight: f64,
+    /// Like `weight`, but each changeset of n files contributes 1/(n-1).
+    pub normalised: f64,
+    /// Commit times of the shared changesets, in no particular order.
+    pub times: Vec<i64>,
+}
+
 #[derive(Default)]
 pub struct GitGraph {
-    pub graph: Graph<GitFile, i64, Undirected>,
+    pub graph: Graph<FileNode, CoChange, Undirected>,
     pub name_table: FxHashMap<String, NodeIndex>,
+    /// Number of changesets the graph was built from.
+    pub changesets: u32,
+}
+
+/// How the co-changes of two files are turned into the similarity of their edge.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Weighting {
+    /// Number of shared changesets, down-weighted for oversized ones.
+    Count,
+    /// Every changeset of n files adds 1/(n-1), so large changesets count less.
+    Normalised,
+    /// Shared changesets over the changesets that contain either file.
+    Jaccard,
+    /// Share of all changesets that contain both files.
+    Support,
+    /// Share of the changesets of the less frequently changed file that also
+    /// contain the other one, i.e. the stronger of the two association rules.
+    Confidence,
+}
+
+/// How strongly two files are coupled, larger means closer.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Similarity(pub f64);
+
+/// The length of an edge for shortest paths, smaller means closer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Distance(pub OrderedFloat<f64>);
+
+impl Add for Distance {
+    type Output = Distance;
+
+    fn add(self, other: Distance) -> Distance {
+        Distance(self.0 + other.0)
+    }
+}
+
+impl Zero for Distance {
+    fn zero() -> Self {
+        Distance(OrderedFloat(0.0))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
 }
 
-pub type ChangeSet = Vec<GitFile>;
+/// How a [`Similarity`] is turned into a [`Distance`]. All of them keep
+/// every edge at a positive length, `min` and `max` being the smallest and
+/// largest similarity in the graph.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Conversion {
+    /// `max - s + min`
+    MaxMinus,
+    /// `1 / s`
+    Reciprocal,
+    /// `-ln(p)` with `p = s / (max + min)`, the similarity read as a smoothed
+    /// probability of the two files changing together.
+    NegativeLog,
+}
 
-pub fn build_graph(changes: Vec<ChangeSet>) -> GitGraph {
-    changes
+impl Conversion {
+    pub fn distance(self, similarity: Similarity, min: Similarity, max: Similarity) -> Distance {
+        let Similarity(s) = similarity;
+        let d = match self {
+            Conversion::MaxMinus => max.0 - s + min.0,
+            Conversion::Reciprocal => 1.0 / s,
+            Conversion::NegativeLog => -(s / (max.0 + min.0)).ln(),
+        };
+        Distance(OrderedFloat(d))
+    }
+}
+
+/// The files changed by a commit.
+#[derive(Clone)]
+pub struct ChangeSet {
+    /// Commit time in seconds since the epoch.
+    pub time: i64,
+    pub files: Vec<GitFile>,
+}
+
+impl ChangeSet {
+    /// Adds the files of another changeset of the same logical change, a file
+    /// in both keeps the status it has here.
+    pub fn absorb(&mut self, other: ChangeSet) {
+        self.time = self.time.max(other.time);
+        for file in other.files {
+            if !self.files.iter().any(|known| known.name == file.name) {
+                self.files.push(file);
+            }
+        }
+    }
+}
+
+/// Maps historic paths to the path the file has at the newest scanned commit.
+pub type RenameMap = FxHashMap<String, String>;
+
+/// What happens to changesets at or above the size limit. Mass edits like
+/// reformatting or dependency bumps couple files that have nothing in common.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Oversized {
+    Drop,
+    Keep,
+    /// Keep them, but scale each edge down so that no file gains more weight
+    /// than it would from the largest changeset below the limit.
+    DownWeight,
+}
+
+pub struct GraphOptions {
+    /// Changesets with this many files or more are oversized.
+    pub changeset_limit: usize,
+    pub oversized: Oversized,
+}
+
+impl Default for GraphOptions {
+    fn default() -> Self {
+        GraphOptions {
+            changeset_limit: 40,
+            oversized: Oversized::Drop,
+        }
+    }
+}
+
+impl GraphOptions {
+    pub fn is_oversized(&self, set: &ChangeSet) -> bool {
+        set.files.len() >= self.changeset_limit
+    }
+
+    /// The weight each pair of files in the changeset adds to their edge.
+    fn edge_weight(&self, set: &ChangeSet) -> Option<f64> {
+        if !self.is_oversized(set) {
+            return Some(1.0);
+        }
+        match self.oversized {
+            Oversized::Drop => None,
+            Oversized::Keep => Some(1.0),
+            Oversized::DownWeight => {
+                let limit_degree = self.changeset_limit.saturating_sub(2).max(1);
+                Some(limit_degree as f64 / (set.files.len() - 1) as f64)
+            }
+        }
+    }
+}
+
+/// Changesets per partial graph. Fixed, so that the order of the nodes and
+/// edges doesn't depend on the number of threads.
+const GRAPH_CHUNK: usize = 64;
+
+pub fn build_graph(changes: Vec<ChangeSet>, options: &GraphOptions) -> GitGraph {
+    let partial: Vec<GitGraph> = changes
         .into_par_iter()
-        .filter(|set| set.len() < 40)
-        .map(GitGraph::from_chageset)
-        .reduce(GitGraph::default, |a, b| a.merge(b))
+        .chunks(GRAPH_CHUNK)
+        .map(|chunk| {
+            chunk
+                .into_iter()
+                .filter_map(|set| options.edge_weight(&set).map(|weight| (set, weight)))
+                .map(|(set, weight)| GitGraph::from_chageset(set, weight))
+                .fold(GitGraph::default(), GitGraph::merge)
+        })
+        .collect();
+    partial
+        .into_iter()
+        .fold(GitGraph::default(), GitGraph::merge)
 }
 
 impl GitGraph {
-    fn from_chageset(changes: ChangeSet) -> Self {
+    fn from_chageset(changes: ChangeSet, weight: f64) -> Self {
         let mut graph = Graph::new_undirected();
         let mut name_table = FxHashMap::default();
         let mut nodes: Vec<NodeIndex> = Vec::new();
 
-        for file in changes {
+        for file in changes.files {
             if name_table.contains_key(&file.name) {
                 continue;
             }
-            let idx = graph.add_node(file.clone());
+            let name = file.name.clone();
+            let idx = graph.add_node(FileNode::new(file, changes.time));
             nodes.push(idx);
-            name_table.insert(file.name, idx);
+            name_table.insert(name, idx);
         }
 
+        let co_change = CoChange {
+            count: 1,
+            weight,
+            normalised: weight / (nodes.len().max(2) - 1) as f64,
+            times: vec![changes.time],
+        };
         for (a, b) in combinations_k_2(nodes.len()) {
-            graph.add_edge(nodes[a], nodes[b], 1);
+            graph.add_edge(nodes[a], nodes[b], co_change.clone());
         }
 
-        GitGraph { graph, name_table }
+        GitGraph {
+            graph,
+            name_table,
+            changesets: 1,
+        }
     }
-    fn merge(self, other: GitGraph) -> Self {
+    /// Joins two graphs, adding up the co-changes of the files they share.
+    pub fn merge(self, other: GitGraph) -> Self {
         if self.len() == 0 {
             return other;
         }
@@ -74,8 +300,10 @@ impl GitGraph {
         let GitGraph {
             graph: old_graph,
             name_table: old_names,
+            changesets: old_changesets,
         } = old;
-        let old_edges: Vec<(NodeIndex, NodeIndex, i64)> = old_graph
+        new.changesets += old_changesets;
+        let old_edges: Vec<(NodeIndex, NodeIndex, CoChange)> = old_graph
             .edge_references()
             .map(|e| (e.source(), e.target(), e.weight().to_owned()))
             .collect();
@@ -86,6 +314,7 @@ impl GitGraph {
             let old_idx = old_names[&name];
 
             if let Some(idx) = new.name_table.get(&name) {
+                new.graph[*idx].absorb(old_node.weight);
                 index_rewrites.insert(old_idx, *idx);
             } else {
                 let idx = new.graph.add_node(old_node.weight);
@@ -99,7 +328,11 @@ impl GitGraph {
             let source = index_rewrites[&source];
             let target = index_rewrites[&target];
             if let Some(edge) = new.graph.find_edge(source, target) {
-                *new.graph.edge_weight_mut(edge).unwrap() += weight;
+                let co_change = new.graph.edge_weight_mut(edge).unwrap();
+                co_change.count += weight.count;
+                co_change.weight += weight.weight;
+                co_change.normalised += weight.normalised;
+                co_change.times.extend(weight.times);
             } else {
                 new.graph.add_edge(source, target, weight);
             }
@@ -107,15 +340,76 @@ impl GitGraph {
 
         new
     }
+    /// The similarity of the two files joined by `edge`.
+    pub fn similarity(&self, edge: EdgeIndex, weighting: Weighting) -> Similarity {
+        let co_change = &self.graph[edge];
+        let (a, b) = self.graph.edge_endpoints(edge).unwrap();
+        let (commits_a, commits_b) = (self.graph[a].commits, self.graph[b].commits);
+        let count = co_change.count as f64;
+
+        Similarity(match weighting {
+            Weighting::Count => co_change.weight,
+            Weighting::Normalised => co_change.normalised,
+            Weighting::Jaccard => count / (commits_a + commits_b - co_change.count) as f64,
+            Weighting::Support => count / self.changesets as f64,
+            Weighting::Confidence => count / commits_a.min(commits_b) as f64,
+        })
+    }
+    /// The node of a file, by its current or any of its former paths.
+    pub fn node(&self, path: &str) -> Result<NodeIndex, RorqualError> {
+        self.name_table
+            .get(path)
+            .copied()
+            .ok_or_else(|| RorqualError::UnknownPath(path.to_string()))
+    }
+    /// The smallest and the largest similarity of all edges.
+    pub fn similarity_range(&self, weighting: Weighting) -> (Similarity, Similarity) {
+        self.graph
+            .edge_indices()
+            .map(|edge| self.similarity(edge, weighting))
+            .fold(
+                (Similarity(f64::INFINITY), Similarity(0.0)),
+                |(min, max), s| (if s < min { s } else { min }, if s > max { s } else { max }),
+            )
+    }
+    /// A view of the graph with edge lengths for shortest path algorithms.
+    /// Node and edge indices are the same as in `self.graph`.
+    pub fn distances(
+        &self,
+        weighting: Weighting,
+        conversion: Conversion,
+    ) -> Graph<(), Distance, Undirected> {
+        let similarities: Vec<Similarity> = self
+            .graph
+            .edge_indices()
+            .map(|edge| self.similarity(edge, weighting))
+            .collect();
+        let (min, max) = self.similarity_range(weighting);
+
+        self.graph.map(
+            |_, _| (),
+            |edge, _| conversion.distance(similarities[edge.index()], min, max),
+        )
+    }
+    /// Points the historic paths of renamed files at the node of their
+    /// current path, unless another file lives at that path today.
+    pub fn fold_renames(&mut self, renames: &RenameMap) {
+        for (old, current) in renames {
+            if self.name_table.contains_key(old) {
+                continue;
+            }
+            if let Some(idx) = self.name_table.get(current).copied() {
+                self.name_table.insert(old.clone(), idx);
+            }
+        }
+    }
     fn len(&self) -> usize {
         self.name_table.len()
     }
 }
 
 fn combinations_k_2(n: usize) -> impl Iterator<Item = (usize, usize)> {
-    (0..n)
-        .map(move |x| (x..n).filter_map(move |y| if x < y { Some((x, y)) } else { None }))
-        .flatten()
+    (0..n).flat_map(move |x| (x..n).filter_map(move |y| if x < y { Some((x, y)) } else { None }))
 }
 
 #[test]
@@ -135,7 +429,11 @@ fn test_build_graph_single_changeset() {
         },
     ];
 
-    let graph = build_graph(vec![first_changeset]);
+    let first_changeset = ChangeSet {
+        time: 0,
+        files: first_changeset,
+    };
+    let graph = build_graph(vec![first_changeset], &GraphOptions::default());
 
     // Check name_table integrity
     assert_eq!(graph.name_table.len(), 3);
@@ -156,7 +454,7 @@ fn test_build_graph_single_changeset() {
         .collect();
     assert_eq!(edges, expected_edges);
     for e in graph.graph.edge_references() {
-        assert_eq!(e.weight().to_owned(), 1);
+        assert_eq!(e.weight().count, 1);
     }
 }
 
@@ -203,7 +501,11 @@ fn test_build_graph_multi_changesets() {
         ],
     ];
 
-    let graph = build_graph(change_sets);
+    let change_sets = change_sets
+        .into_iter()
+        .map(|files| ChangeSet { time: 0, files })
+        .collect();
+    let graph = build_graph(change_sets, &GraphOptions::default());
 
     assert_eq!(graph.name_table.len(), 4);
     assert_eq!(graph.graph.node_count(), graph.name_table.len());
@@ -223,15 +525,211 @@ fn test_build_graph_multi_changesets() {
     let b_idx = graph.name_table["b"];
     let c_idx = graph.name_table["c"];
     let b_c_edge = graph.graph.find_edge(b_idx, c_idx).unwrap();
-    assert_eq!(graph.graph[b_c_edge], 2);
+    assert_eq!(graph.graph[b_c_edge].count, 2);
     for e in graph.graph.edge_references() {
         if e.id() == b_c_edge {
             continue;
         }
-        assert_eq!(e.weight().to_owned(), 1);
+        assert_eq!(e.weight().count, 1);
+    }
+}
+
+#[test]
+fn test_fold_renames() {
+    let change_sets = vec![files(&["new", "other"])];
+    let mut renames = RenameMap::default();
+    renames.insert("old".to_string(), "new".to_string());
+    renames.insert("older".to_string(), "new".to_string());
+    renames.insert("other".to_string(), "elsewhere".to_string());
+
+    let mut graph = build_graph(change_sets, &GraphOptions::default());
+    graph.fold_renames(&renames);
+
+    assert_eq!(graph.graph.node_count(), 2);
+    assert_eq!(graph.name_table["old"], graph.name_table["new"]);
+    assert_eq!(graph.name_table["older"], graph.name_table["new"]);
+    assert_ne!(graph.name_table["other"], graph.name_table["new"]);
+    assert!(!graph.name_table.contains_key("elsewhere"));
+    assert_eq!(graph.node("old").unwrap(), graph.name_table["new"]);
+    assert!(matches!(
+        graph.node("elsewhere"),
+        Err(RorqualError::UnknownPath(path)) if path == "elsewhere"
+    ));
+}
+
+#[cfg(test)]
+fn files(names: &[&str]) -> ChangeSet {
+    ChangeSet {
+        time: 0,
+        files: names
+            .iter()
+            .map(|name| GitFile {
+                status: Status::Modified,
+                name: name.to_string(),
+            })
+            .collect(),
     }
 }
 
+#[test]
+fn test_oversized_changesets() {
+    let change_sets = vec![files(&["a", "b"]), files(&["a", "b", "c", "d", "e"])];
+    let mut options = GraphOptions {
+        changeset_limit: 4,
+        oversized: Oversized::Drop,
+    };
+
+    let graph = build_graph(change_sets.clone(), &options);
+    assert_eq!(graph.graph.node_count(), 2);
+    assert_eq!(graph.graph.edge_count(), 1);
+
+    options.oversized = Oversized::Keep;
+    let graph = build_graph(change_sets.clone(), &options);
+    assert_eq!(graph.graph.edge_count(), 10);
+    let a_b = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["b"])
+        .unwrap();
+    assert_eq!(
+        graph.graph[a_b],
+        CoChange {
+            count: 2,
+            weight: 2.0,
+            normalised: 1.25,
+            times: vec![0, 0],
+        }
+    );
+
+    options.oversized = Oversized::DownWeight;
+    let graph = build_graph(change_sets, &options);
+    assert_eq!(graph.graph.edge_count(), 10);
+    let a_b = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["b"])
+        .unwrap();
+    assert_eq!(
+        graph.graph[a_b],
+        CoChange {
+            count: 2,
+            weight: 1.5,
+            normalised: 1.125,
+            times: vec![0, 0],
+        }
+    );
+    let d_e = graph
+        .graph
+        .find_edge(graph.name_table["d"], graph.name_table["e"])
+        .unwrap();
+    assert_eq!(
+        graph.graph[d_e],
+        CoChange {
+            count: 1,
+            weight: 0.5,
+            normalised: 0.125,
+            times: vec![0],
+        }
+    );
+}
+
+#[test]
+fn test_weightings() {
+    let change_sets = vec![
+        files(&["a", "b"]),
+        files(&["a", "b", "c"]),
+        files(&["a", "d"]),
+        files(&["e"]),
+    ];
+    let graph = build_graph(change_sets, &GraphOptions::default());
+    assert_eq!(graph.changesets, 4);
+    assert_eq!(graph.graph[graph.name_table["a"]].commits, 3);
+
+    let a_b = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["b"])
+        .unwrap();
+    assert_eq!(graph.similarity(a_b, Weighting::Count), Similarity(2.0));
+    assert_eq!(
+        graph.similarity(a_b, Weighting::Normalised),
+        Similarity(1.5)
+    );
+    assert_eq!(
+        graph.similarity(a_b, Weighting::Jaccard),
+        Similarity(2.0 / 3.0)
+    );
+    assert_eq!(graph.similarity(a_b, Weighting::Support), Similarity(0.5));
+    assert_eq!(
+        graph.similarity(a_b, Weighting::Confidence),
+        Similarity(1.0)
+    );
+
+    let a_d = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["d"])
+        .unwrap();
+    assert_eq!(
+        graph.similarity(a_d, Weighting::Jaccard),
+        Similarity(1.0 / 3.0)
+    );
+    assert_eq!(
+        graph.similarity(a_d, Weighting::Confidence),
+        Similarity(1.0)
+    );
+}
+
+#[test]
+fn test_distances_leave_graph_untouched() {
+    let change_sets = vec![files(&["a", "b"]), files(&["a", "b"]), files(&["b", "c"])];
+    let graph = build_graph(change_sets, &GraphOptions::default());
+    let a_b = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["b"])
+        .unwrap();
+    let b_c = graph
+        .graph
+        .find_edge(graph.name_table["b"], graph.name_table["c"])
+        .unwrap();
+
+    let length = |conversion, edge: EdgeIndex| {
+        let Distance(OrderedFloat(d)) = graph.distances(Weighting::Count, conversion)[edge];
+        d
+    };
+    assert_eq!(length(Conversion::MaxMinus, a_b), 1.0);
+    assert_eq!(length(Conversion::MaxMinus, b_c), 2.0);
+    assert_eq!(length(Conversion::Reciprocal, a_b), 0.5);
+    assert_eq!(length(Conversion::Reciprocal, b_c), 1.0);
+    assert_eq!(length(Conversion::NegativeLog, a_b), -(2.0f64 / 3.0).ln());
+    assert_eq!(length(Conversion::NegativeLog, b_c), -(1.0f64 / 3.0).ln());
+
+    assert_eq!(graph.graph[a_b].weight, 2.0);
+}
+
+#[test]
+fn test_change_times() {
+    let mut newest = files(&["a", "b"]);
+    newest.time = 300;
+    newest.files[0].status = Status::Deleted;
+    let mut oldest = files(&["a", "b", "c"]);
+    oldest.time = 100;
+    let mut middle = files(&["a", "c"]);
+    middle.time = 200;
+
+    let graph = build_graph(vec![newest, oldest, middle], &GraphOptions::default());
+
+    let a = &graph.graph[graph.name_table["a"]];
+    assert_eq!((a.first_change, a.last_change), (100, 300));
+    assert!(matches!(a.status, Status::Deleted));
+    let c = &graph.graph[graph.name_table["c"]];
+    assert_eq!((c.first_change, c.last_change), (100, 200));
+
+    let a_b = graph
+        .graph
+        .find_edge(graph.name_table["a"], graph.name_table["b"])
+        .unwrap();
+    let mut times = graph.graph[a_b].times.clone();
+    times.sort_unstable();
+    assert_eq!(times, vec![100, 300]);
+}
+
 #[test]
 fn test_combinations() {
     let c: Vec<(usize, usize)> = combinations_k_2(4).collect();
@@ -239,3 +737,38 @@ fn test_combinations() {
     assert_eq!(c.len(), expected.len());
     assert_eq!(c, expected);
 }
+
+#[test]
+fn test_build_graph_independent_of_threads() {
+    let changesets: Vec<ChangeSet> = (0..300)
+        .map(|i| ChangeSet {
+            time: i,
+            files: [i % 7, i % 11, i % 13 + 20]
+                .iter()
+                .map(|n| GitFile {
+                    status: Status::Modified,
+                    name: n.to_string(),
+                })
+                .collect(),
+        })
+        .collect();
+    let build = |threads| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let graph = pool.install(|| build_graph(changesets.clone(), &GraphOptions::default()));
+        let nodes: Vec<String> = graph.graph.node_weights().map(|n| n.name.clone()).collect();
+        let edges: Vec<(usize, usize, CoChange)> = graph
+            .graph
+            .edge_references()
+            .map(|e| (e.source().index(), e.target().index(), e.weight().clone()))
+            .collect();
+        (nodes, edges)
+    };
+
+    let single = build(1);
+    assert_eq!(single.0.len(), 24);
+    assert_eq!(build(3), single);
+    assert_eq!(build(8), single);
+}
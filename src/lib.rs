@@ -0,0 +1,51 @@
+//! Rorqual turns the history of a git repository into an undirected graph:
+//! files are the nodes, and an edge joins two files every time they were
+//! changed together.
+//!
+//! A [`Scan`] walks the repository and builds the graph, the resulting
+//! [`Analysis`] answers questions about it:
+//!
+//! ```no_run
+//! use rorqual::{CommitSelection, Conversion, Scan, Weighting};
+//!
+//! # fn main() -> Result<(), rorqual::RorqualError> {
+//! let now = chrono::Utc::now();
+//! let analysis = Scan::new("path/to/repo")
+//!     .since(rorqual::parse_date("6.months.ago", now)?)
+//!     .selection(CommitSelection::NonMerges)
+//!     .changeset_limit(40)
+//!     .run()?;
+//! let centrality = analysis.centrality(Some((Weighting::Jaccard, Conversion::MaxMinus)));
+//! for (node, betweenness) in centrality {
+//!     println!("{} {}", analysis.graph.graph[node].name, betweenness);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The steps are available on their own as well: [`repo_to_changesets`]
+//! extracts the changesets a [`GitFilter`] selects, [`build_graph`] turns them
+//! into a [`GitGraph`] and the [`analyser`] computes centralities on it.
+pub mod analyser;
+mod attributes;
+pub mod error;
+pub mod export;
+pub mod git2graph;
+pub mod git_graph;
+pub mod neighbourhood;
+pub mod path_filter;
+mod scan;
+pub mod store;
+
+pub use analyser::centrality::{betweenness_centrality, betweenness_centrality_unweighted};
+pub use error::RorqualError;
+pub use git2graph::{
+    parse_date, parse_end_date, repo_to_changesets, CommitInfo, CommitSelection, Exclusions,
+    GitFilter, History, IdentityRule, MergeDiff, Role, Stop,
+};
+pub use git_graph::{
+    build_graph, ChangeSet, Conversion, GitGraph, GraphOptions, Oversized, Weighting,
+};
+pub use neighbourhood::NeighbourQuery;
+pub use path_filter::PathFilter;
+pub use scan::{compare, Analysis, CentralityChange, Scan, Update};
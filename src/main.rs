@@ -1,165 +1,890 @@
-mod analyser;
-mod git2graph;
-mod git_graph;
-
-use chrono::{
-    format::{self, Parsed, StrftimeItems},
-    Utc,
+use chrono::Utc;
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, SubCommand};
+use petgraph::graph::NodeIndex;
+use regex::{Regex, RegexSet};
+use rorqual::export::{write_dot, write_gexf, write_graphml, write_neighbourhood};
+use rorqual::{
+    compare, parse_date, parse_end_date, store, Analysis, CommitSelection, Conversion, GitFilter,
+    IdentityRule, MergeDiff, NeighbourQuery, Oversized, PathFilter, Role, RorqualError, Scan, Stop,
+    Update, Weighting,
 };
-use chrono_tz::UTC;
-use clap::{App, Arg};
-use git2graph::GitFilter;
-use git_graph::GitGraph;
-use petgraph::{graph::NodeIndex, visit::IntoNeighbors};
-use regex::RegexSet;
+use rustc_hash::FxHashMap;
 use std::{
-    collections::{HashSet, VecDeque},
     env,
+    error::Error,
     fs::File,
-    io::{stdout, Write},
+    io::{self, stdout, Write},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    time::Instant,
 };
 
-fn main() -> std::io::Result<()> {
-    let matches = App::new("Rorqual")
+fn main() {
+    let matches = app().get_matches();
+    let debug = matches.is_present("debug");
+    if let Err(e) = run(&matches, debug) {
+        eprintln!("error: {}", e);
+        if debug {
+            let mut source = e.source();
+            while let Some(cause) = source {
+                eprintln!("caused by: {}", cause);
+                source = cause.source();
+            }
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("Rorqual")
         .version("0.1")
         .author("CR")
         .about("Graph analysis for git repos")
-        .arg(Arg::with_name("debug").short("d").long("debug"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .global(true)
+                .help("prints how long each step took and the causes of errors"),
+        )
         .arg(
             Arg::with_name("repo")
                 .long("repo")
                 .takes_value(true)
+                .global(true)
                 .help("path to the repo"),
         )
         .arg(
-            Arg::with_name("start_time")
-                .long("start-time")
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
                 .takes_value(true)
-                .help("time of eraliest commits"),
+                .validator(is_number::<usize>)
+                .global(true)
+                .help("number of threads diffing commits and computing centralities, defaults to one per core"),
         )
-        .arg(
-            Arg::with_name("filter")
-                .long("filter")
-                .takes_value(true)
-                .help("regex of path to ignore"),
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("Builds the graph, prints what was left out and saves it for the other commands")
+                .args(&history_args(true))
+                .args(&graph_args())
+                .arg(
+                    Arg::with_name("update")
+                        .long("update")
+                        .requires("output")
+                        .conflicts_with_all(SCAN_OPTIONS)
+                        .help("only walks the commits added since the scan saved to --output, with its settings"),
+                )
+                .arg(output_arg(
+                    "file to save the scan to, nothing is saved without one",
+                )),
         )
-        .arg(
-            Arg::with_name("report")
-                .long("report")
-                .help("prints a report of the analysis"),
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Prints the betweenness centrality of every file as CSV")
+                .arg(scan_arg())
+                .args(&history_args(true))
+                .args(&graph_args())
+                .args(&centrality_args())
+                .arg(output_arg("file to write the report to instead of stdout")),
         )
-        .arg(
-            Arg::with_name("neighbours")
-                .long("neighbours")
-                .takes_value(true)
-                .help("paths to get neighbourhood of"),
+        .subcommand(
+            SubCommand::with_name("neighbours")
+                .about("Writes the neighbourhood of some files for Graphviz")
+                .arg(
+                    Arg::with_name("paths")
+                        .required(true)
+                        .multiple(true)
+                        .help("paths to get the neighbourhood of"),
+                )
+                .arg(scan_arg())
+                .args(&history_args(true))
+                .args(&graph_args())
+                .arg(weighting_arg())
+                .arg(min_weight_arg())
+                .arg(
+                    Arg::with_name("depth")
+                        .long("depth")
+                        .takes_value(true)
+                        .default_value("1")
+                        .validator(is_number::<u64>)
+                        .help("number of hops the neighbourhood reaches out"),
+                )
+                .arg(
+                    Arg::with_name("top_k")
+                        .long("top-k")
+                        .takes_value(true)
+                        .validator(is_number::<usize>)
+                        .help(
+                            "only follow the k heaviest edges of every file into the neighbourhood",
+                        ),
+                )
+                .arg(output_arg(
+                    "file to write the neighbourhood to instead of stdout",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes the whole graph as GraphML, GEXF or Graphviz dot")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["graphml", "gexf", "dot"])
+                        .default_value("graphml")
+                        .help("format to write the graph in"),
+                )
+                .arg(scan_arg())
+                .args(&history_args(true))
+                .args(&graph_args())
+                .args(&centrality_args())
+                .arg(
+                    Arg::with_name("centrality")
+                        .long("centrality")
+                        .help("adds the betweenness centrality of every file to the GraphML"),
+                )
+                .arg(
+                    Arg::with_name("cluster_depth")
+                        .long("cluster-depth")
+                        .takes_value(true)
+                        .default_value("1")
+                        .validator(is_number::<usize>)
+                        .help("number of directory levels the dot output clusters files by"),
+                )
+                .arg(min_weight_arg())
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("30")
+                        .validator(is_interval)
+                        .help("length in days of the time slices of the gexf timeline"),
+                )
+                .arg(output_arg("file to write the graph to instead of stdout")),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about(
+                    "Prints how the centrality of every file differs between two revisions as CSV",
+                )
+                .arg(
+                    Arg::with_name("old").required(true).help(
+                        "revision, range or ref glob of the old graph, e.g. v1.2 or v1.1..v1.2",
+                    ),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .help("revision, range or ref glob of the new graph"),
+                )
+                .arg(
+                    Arg::with_name("scans")
+                        .long("scans")
+                        .conflicts_with_all(SCAN_OPTIONS)
+                        .help("reads <old> and <new> as files saved by rorqual scan"),
+                )
+                .args(&history_args(false))
+                .args(&graph_args())
+                .args(&centrality_args())
+                .arg(output_arg(
+                    "file to write the comparison to instead of stdout",
+                )),
         )
-        .get_matches();
+}
+
+/// The options that pick the commits and files the graph is built from.
+fn history_args(revisions: bool) -> Vec<Arg<'static, 'static>> {
+    let mut args = vec![
+        Arg::with_name("start_time")
+            .long("start-time")
+            .alias("since")
+            .takes_value(true)
+            .help("time of eraliest commits, e.g. 2021-01-31, 6.months.ago or 90d"),
+        Arg::with_name("until")
+            .long("until")
+            .takes_value(true)
+            .help("time of the latest commits, in the same formats as --start-time"),
+        Arg::with_name("include")
+            .long("include")
+            .takes_value(true)
+            .multiple(true)
+            .help("only keep paths matching these globs, or regexes prefixed with re:"),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .takes_value(true)
+            .multiple(true)
+            .help("leave out paths matching these globs, or regexes prefixed with re:"),
+        Arg::with_name("filter_file")
+            .long("filter-file")
+            .takes_value(true)
+            .help("file with exclude patterns, and include patterns prefixed with +, relative to the repo"),
+        Arg::with_name("include_author")
+            .long("include-author")
+            .takes_value(true)
+            .multiple(true)
+            .help("only keep commits whose author's \"Name <email>\" matches one of these regexes"),
+        Arg::with_name("exclude_author")
+            .long("exclude-author")
+            .takes_value(true)
+            .multiple(true)
+            .help("leave out commits whose author's \"Name <email>\" matches one of these regexes"),
+        Arg::with_name("include_committer")
+            .long("include-committer")
+            .takes_value(true)
+            .multiple(true)
+            .help("only keep commits whose committer's \"Name <email>\" matches one of these regexes"),
+        Arg::with_name("exclude_committer")
+            .long("exclude-committer")
+            .takes_value(true)
+            .multiple(true)
+            .help("leave out commits whose committer's \"Name <email>\" matches one of these regexes"),
+        Arg::with_name("exclude_message")
+            .long("exclude-message")
+            .takes_value(true)
+            .multiple(true)
+            .help("leave out commits whose message matches one of these regexes, e.g. ^Revert"),
+        Arg::with_name("ticket_pattern")
+            .long("ticket-pattern")
+            .takes_value(true)
+            .help("regex for ticket IDs in commit messages, commits with the same ID form one changeset"),
+        Arg::with_name("author_window")
+            .long("author-window")
+            .takes_value(true)
+            .validator(is_minutes)
+            .help("minutes within which consecutive commits of the same author form one changeset"),
+        Arg::with_name("ignore_revs_file")
+            .long("ignore-revs-file")
+            .takes_value(true)
+            .help("file listing commits to leave out, relative to the repo, defaults to .git-blame-ignore-revs"),
+        Arg::with_name("keep_ignored")
+            .long("keep-ignored")
+            .help("keep generated, vendored and -diff files as well as the ones in .rorqualignore"),
+        Arg::with_name("merge_diff")
+            .long("merge-diff")
+            .takes_value(true)
+            .possible_values(&["first-parent", "merge-base"])
+            .default_value("first-parent")
+            .help("what merge commits are diffed against"),
+        Arg::with_name("commits")
+            .long("commits")
+            .takes_value(true)
+            .possible_values(&[
+                "all",
+                "merges",
+                "first-parent",
+                "non-merges",
+                "pull-requests",
+            ])
+            .default_value("all")
+            .help("which commits are analysed"),
+        Arg::with_name("merge_pattern")
+            .long("merge-pattern")
+            .takes_value(true)
+            .default_value("^Merge pull request")
+            .help("regex of merge messages selected by --commits pull-requests"),
+        Arg::with_name("max_commits")
+            .long("max-commits")
+            .takes_value(true)
+            .validator(is_number::<u32>)
+            .help("stops the walk after this many selected commits"),
+    ];
+    if revisions {
+        args.push(
+            Arg::with_name("revisions")
+                .long("revisions")
+                .takes_value(true)
+                .multiple(true)
+                .help("revisions, ranges or ref globs to walk instead of HEAD, e.g. v1.2..v1.3, refs/heads/release/* or ^refs/heads/old"),
+        );
+    }
+    args
+}
+
+/// The options that decide how changesets become edges.
+fn graph_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("changeset_limit")
+            .long("changeset-limit")
+            .takes_value(true)
+            .default_value("40")
+            .validator(is_number::<usize>)
+            .help("changesets with this many files or more are oversized"),
+        Arg::with_name("oversized")
+            .long("oversized")
+            .takes_value(true)
+            .possible_values(&["drop", "keep", "down-weight"])
+            .default_value("drop")
+            .help("what to do with oversized changesets"),
+    ]
+}
+
+fn weighting_arg() -> Arg<'static, 'static> {
+    Arg::with_name("weighting")
+        .long("weighting")
+        .takes_value(true)
+        .possible_values(&["count", "normalised", "jaccard", "support", "confidence"])
+        .default_value("count")
+        .help("how co-changes are turned into edge weights")
+}
+
+fn min_weight_arg() -> Arg<'static, 'static> {
+    Arg::with_name("min_weight")
+        .long("min-weight")
+        .takes_value(true)
+        .default_value("0")
+        .validator(is_number::<f64>)
+        .help("edges with a lower weight are left out of the dot output and the neighbourhood")
+}
+
+fn centrality_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        weighting_arg(),
+        Arg::with_name("distance")
+            .long("distance")
+            .takes_value(true)
+            .possible_values(&["max-minus", "reciprocal", "neg-log"])
+            .default_value("max-minus")
+            .help("how edge weights are turned into path lengths for the centrality"),
+        Arg::with_name("unweighted")
+            .long("unweighted")
+            .help("counts hops for the centrality and ignores edge weights"),
+    ]
+}
+
+/// The options that only matter when the history is walked.
+const SCAN_OPTIONS: &[&str] = &[
+    "start_time",
+    "until",
+    "include",
+    "exclude",
+    "filter_file",
+    "include_author",
+    "exclude_author",
+    "include_committer",
+    "exclude_committer",
+    "exclude_message",
+    "ticket_pattern",
+    "author_window",
+    "ignore_revs_file",
+    "keep_ignored",
+    "merge_diff",
+    "commits",
+    "merge_pattern",
+    "max_commits",
+    "revisions",
+    "changeset_limit",
+    "oversized",
+];
 
+fn scan_arg() -> Arg<'static, 'static> {
+    Arg::with_name("scan")
+        .long("scan")
+        .takes_value(true)
+        .conflicts_with_all(SCAN_OPTIONS)
+        .help("file saved by rorqual scan to read the graph from instead of walking the history")
+}
+
+/// Lets clap reject values of number arguments that don't parse as a `T`.
+fn is_number<T: FromStr>(value: String) -> Result<(), String> {
+    match value.parse::<T>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("{} is not a valid number", value)),
+    }
+}
+
+/// Lets clap reject a `--interval` that isn't a positive number of days.
+fn is_interval(value: String) -> Result<(), String> {
+    match value.parse::<i64>() {
+        Ok(days) if days > 0 && days.checked_mul(24 * 60 * 60).is_some() => Ok(()),
+        _ => Err(format!("{} is not a positive number of days", value)),
+    }
+}
+
+/// Lets clap reject an `--author-window` that isn't a number of minutes.
+fn is_minutes(value: String) -> Result<(), String> {
+    match value.parse::<i64>() {
+        Ok(minutes) if minutes >= 0 && minutes.checked_mul(60).is_some() => Ok(()),
+        _ => Err(format!("{} is not a number of minutes", value)),
+    }
+}
+
+/// The value of a number argument, or `None` if it wasn't given.
+fn number<T: FromStr>(args: &ArgMatches, name: &str) -> Option<T> {
+    args.value_of(name)
+        .map(|_| value_t!(args, name, T).unwrap_or_else(|e| e.exit()))
+}
+
+fn output_arg(help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("output")
+        .short("o")
+        .long("output")
+        .takes_value(true)
+        .help(help)
+}
+
+fn run(matches: &ArgMatches, debug: bool) -> Result<(), RorqualError> {
+    if let Some(jobs) = number(matches, "jobs") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .map_err(io::Error::other)?;
+    }
     let repo_path = if let Some(rel_path) = matches.value_of("repo") {
         env::current_dir()?.join(rel_path)
     } else {
         env::current_dir()?
     };
 
-    let mut p = Parsed::default();
+    match matches.subcommand() {
+        ("scan", Some(args)) => {
+            let analysis = if args.is_present("update") {
+                let path = Path::new(args.value_of("output").unwrap());
+                let saved = timed(debug, "load", || store::load(path))?;
+                let (analysis, update) = timed(debug, "update", || saved.update(&repo_path))?;
+                match update {
+                    Update::Merged(commits) => eprintln!("Added commits: {}", commits),
+                    Update::Rescanned(reason) => eprintln!("Scanned again, {}", reason),
+                }
+                analysis
+            } else {
+                scan(args, &repo_path, git_filter(args, &repo_path)?, debug)?
+            };
+            summary(&analysis);
+            if let Some(path) = args.value_of("output") {
+                timed(debug, "save", || store::save(&analysis, Path::new(path)))?;
+            }
+        }
+        ("report", Some(args)) => {
+            let analysis = saved_or_scan(args, &repo_path, debug)?;
+            let centrality = timed(debug, "centrality", || analysis.centrality(distance(args)));
+            bc_report(&analysis, &centrality, &mut output_writer(args)?)?;
+            summary(&analysis);
+        }
+        ("neighbours", Some(args)) => {
+            let analysis = saved_or_scan(args, &repo_path, debug)?;
+            let query = NeighbourQuery {
+                weighting: weighting(args),
+                min_weight: number(args, "min_weight").unwrap_or_default(),
+                top_k: number(args, "top_k"),
+            };
+            let depth = number(args, "depth").unwrap_or(1);
+            let paths: Vec<&str> = args.values_of("paths").unwrap().collect();
+            let inside = analysis.neighbourhood(&paths, depth, &query)?;
+            write_neighbourhood(&analysis.graph, &query, &inside, &mut output_writer(args)?)?;
+        }
+        ("export", Some(args)) => {
+            let analysis = saved_or_scan(args, &repo_path, debug)?;
+            let graph = &analysis.graph;
+            let weighting = weighting(args);
+            let mut writer = output_writer(args)?;
+            match args.value_of("format") {
+                Some("gexf") => {
+                    let days: i64 = number(args, "interval").unwrap_or(30);
+                    write_gexf(graph, weighting, days * 24 * 60 * 60, &mut writer)?;
+                }
+                Some("dot") => {
+                    let depth = number(args, "cluster_depth").unwrap_or(1);
+                    let min_weight = number(args, "min_weight").unwrap_or_default();
+                    write_dot(graph, weighting, depth, min_weight, &mut writer)?;
+                }
+                _ => {
+                    let centrality = if args.is_present("centrality") {
+                        Some(timed(debug, "centrality", || {
+                            analysis.centrality(distance(args))
+                        }))
+                    } else {
+                        None
+                    };
+                    write_graphml(graph, weighting, centrality.as_ref(), &mut writer)?;
+                }
+            }
+        }
+        ("compare", Some(args)) => {
+            let mut analyses = Vec::new();
+            for revision in &[args.value_of("old").unwrap(), args.value_of("new").unwrap()] {
+                if args.is_present("scans") {
+                    analyses.push(timed(debug, "load", || store::load(Path::new(revision)))?);
+                } else {
+                    let mut filter = git_filter(args, &repo_path)?;
+                    filter.revisions = vec![revision.to_string()];
+                    analyses.push(scan(args, &repo_path, filter, debug)?);
+                }
+            }
+            let changes = timed(debug, "centrality", || {
+                compare(&analyses[0], &analyses[1], distance(args))
+            });
+            let mut writer = output_writer(args)?;
+            for change in changes {
+                let format = |betweenness: Option<f64>| {
+                    betweenness.map_or_else(String::new, |bc| format!("{:.6}", bc))
+                };
+                writeln!(
+                    writer,
+                    "\"{}\",{},{},{:.6}",
+                    change.path,
+                    format(change.old),
+                    format(change.new),
+                    change.delta()
+                )?;
+            }
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
 
-    format::parse(
-        &mut p,
-        matches.value_of("start_time").unwrap(),
-        StrftimeItems::new("%Y-%m-%d"),
-    )
-    .unwrap();
-    p.hour_mod_12 = Some(0);
-    p.hour_div_12 = Some(0);
-    p.minute = Some(0);
-    p.second = Some(0);
+    Ok(())
+}
 
-    let path_filters = if let Some(filters) = matches.values_of("filter") {
-        RegexSet::new(filters.collect::<Vec<&str>>()).unwrap()
-    } else {
-        RegexSet::empty()
-    };
+fn git_filter(args: &ArgMatches, repo_path: &Path) -> Result<GitFilter, RorqualError> {
+    let now = Utc::now();
+    let start_date = args
+        .value_of("start_time")
+        .map(|date| parse_date(date, now))
+        .transpose()?;
+    let end_date = args
+        .value_of("until")
+        .map(|date| parse_end_date(date, now))
+        .transpose()?;
 
-    let filter = GitFilter {
-        start_date: Some(p.to_datetime_with_timezone(&Utc).unwrap()),
-        max_commits: Some(40),
-        path_filters: path_filters,
+    let patterns = |name| {
+        args.values_of(name)
+            .map_or_else(Vec::new, Iterator::collect)
     };
-
-    let changes = git2graph::repo_to_changesets(repo_path, &filter);
-    let mut graph = git_graph::build_graph(changes);
-
-    // Hack!! replace int weight with aliases: Distance and Similarity
-    // Both are ints but Similarity has the Ord impl reversed
-    let max_count = *graph.graph.edge_weights().max().unwrap();
-    for count in graph.graph.edge_weights_mut() {
-        *count = max_count - *count;
+    let mut path_filter = PathFilter::new(&patterns("include"), &patterns("exclude"))?;
+    if let Some(file) = args.value_of("filter_file") {
+        path_filter = path_filter.extend(PathFilter::from_file(&repo_path.join(file))?);
     }
 
-    if matches.is_present("report") {
-        bc_report(&graph);
+    let mut identities = Vec::new();
+    for (arg, role, exclude) in &[
+        ("include_author", Role::Author, false),
+        ("exclude_author", Role::Author, true),
+        ("include_committer", Role::Committer, false),
+        ("exclude_committer", Role::Committer, true),
+    ] {
+        for pattern in args.values_of(arg).into_iter().flatten() {
+            identities.push(IdentityRule {
+                role: *role,
+                pattern: Regex::new(pattern)?,
+                exclude: *exclude,
+            });
+        }
     }
 
-    if let Some(nb) = matches.value_of("neighbours") {
-        println!("graph {{");
-        let current = graph.name_table[nb];
-        write_node(current, &graph, &mut stdout());
-        for nb in graph.graph.neighbors(current) {
-            write_node(nb, &graph, &mut stdout());
-            write_edge(current, nb, &graph, &mut stdout());
+    let selection = match args.value_of("commits") {
+        Some("merges") => CommitSelection::Merges,
+        Some("first-parent") => CommitSelection::FirstParent,
+        Some("non-merges") => CommitSelection::NonMerges,
+        Some("pull-requests") => {
+            CommitSelection::PullRequests(Regex::new(args.value_of("merge_pattern").unwrap())?)
         }
-        println!("}}");
+        _ => CommitSelection::All,
+    };
+
+    Ok(GitFilter {
+        start_date,
+        end_date,
+        revisions: args
+            .values_of("revisions")
+            .map_or_else(Vec::new, |specs| specs.map(String::from).collect()),
+        max_commits: number(args, "max_commits"),
+        path_filter,
+        skip_ignored: !args.is_present("keep_ignored"),
+        merge_diff: match args.value_of("merge_diff") {
+            Some("merge-base") => MergeDiff::MergeBase,
+            _ => MergeDiff::FirstParent,
+        },
+        selection,
+        identities,
+        message_excludes: RegexSet::new(args.values_of("exclude_message").into_iter().flatten())?,
+        ticket_pattern: args
+            .value_of("ticket_pattern")
+            .map(Regex::new)
+            .transpose()?,
+        author_window: number(args, "author_window").map(|minutes: i64| minutes * 60),
+        ignore_revs_file: args
+            .value_of("ignore_revs_file")
+            .map(|file| repo_path.join(file)),
+    })
+}
+
+/// The analysis saved to `--scan`, or a new scan of the repo.
+fn saved_or_scan(
+    args: &ArgMatches,
+    repo_path: &Path,
+    debug: bool,
+) -> Result<Analysis, RorqualError> {
+    match args.value_of("scan") {
+        Some(path) => timed(debug, "load", || store::load(Path::new(path))),
+        None => scan(args, repo_path, git_filter(args, repo_path)?, debug),
     }
+}
 
-    Ok(())
+fn scan(
+    args: &ArgMatches,
+    repo_path: &Path,
+    filter: GitFilter,
+    debug: bool,
+) -> Result<Analysis, RorqualError> {
+    let oversized = match args.value_of("oversized") {
+        Some("keep") => Oversized::Keep,
+        Some("down-weight") => Oversized::DownWeight,
+        _ => Oversized::Drop,
+    };
+    let scan = Scan::new(PathBuf::from(repo_path))
+        .filter(filter)
+        .changeset_limit(number(args, "changeset_limit").unwrap_or(40))
+        .oversized(oversized);
+    timed(debug, "scan", || scan.run())
 }
 
-fn write_node<W: Write>(idx: NodeIndex, graph: &GitGraph, writer: &mut W) {
-    let name = &graph.graph[idx].name;
-    writeln!(
-        writer,
-        "\"{:?}\" [label=\"{}\" fixedsize=true fontsize=7]",
-        idx, name
-    )
-    .unwrap();
+fn weighting(args: &ArgMatches) -> Weighting {
+    match args.value_of("weighting") {
+        Some("normalised") => Weighting::Normalised,
+        Some("jaccard") => Weighting::Jaccard,
+        Some("support") => Weighting::Support,
+        Some("confidence") => Weighting::Confidence,
+        _ => Weighting::Count,
+    }
 }
 
-fn write_edge<W: Write>(from: NodeIndex, to: NodeIndex, graph: &GitGraph, writer: &mut W) {
-    let edge_idx = graph.graph.find_edge(from, to).unwrap();
-    writeln!(
-        writer,
-        "\"{:?}\" -- \"{:?}\" [weight={}]",
-        from, to, graph.graph[edge_idx]
-    );
+fn distance(args: &ArgMatches) -> Option<(Weighting, Conversion)> {
+    if args.is_present("unweighted") {
+        return None;
+    }
+    let conversion = match args.value_of("distance") {
+        Some("reciprocal") => Conversion::Reciprocal,
+        Some("neg-log") => Conversion::NegativeLog,
+        _ => Conversion::MaxMinus,
+    };
+    Some((weighting(args), conversion))
 }
 
-fn recurse_neighbors(idx: NodeIndex, graph: &GitGraph, depth: u64) -> HashSet<NodeIndex> {
-    if depth == 0 {
-        HashSet::default()
-    } else {
-        let mut result: HashSet<NodeIndex> = graph
-            .graph
-            .neighbors(idx)
-            .flat_map(|n| recurse_neighbors(n, graph, depth - 1))
-            .collect();
-        result.insert(idx);
-        return result;
+/// Runs `step` and, with `--debug`, prints how long it took.
+fn timed<T, F: FnOnce() -> T>(debug: bool, name: &str, step: F) -> T {
+    let start = Instant::now();
+    let result = step();
+    if debug {
+        eprintln!("debug: {} took {:.2?}", name, start.elapsed());
     }
+    result
 }
 
-fn bc_report(graph: &GitGraph) {
-    let mut bc = analyser::centrality::betweenness_centrality(&graph.graph);
+fn output_writer(args: &ArgMatches) -> io::Result<Box<dyn Write>> {
+    Ok(match args.value_of("output") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(stdout()),
+    })
+}
 
-    for (vertex, betweenness) in bc.into_iter() {
-        if let Some(git_file) = graph.graph.node_weight(vertex) {
-            println!("\"{}\",{:.6}", git_file.name, betweenness);
+fn bc_report<W: Write>(
+    analysis: &Analysis,
+    centrality: &FxHashMap<NodeIndex, f64>,
+    writer: &mut W,
+) -> io::Result<()> {
+    for (vertex, betweenness) in centrality.iter() {
+        if let Some(git_file) = analysis.graph.graph.node_weight(*vertex) {
+            writeln!(writer, "\"{}\",{:.6}", git_file.name, betweenness)?;
         }
     }
+    Ok(())
+}
 
+fn summary(analysis: &Analysis) {
+    let excluded = &analysis.excluded;
+    let options = &analysis.options;
     eprintln!(
         "Total nodes: {} edges: {}",
-        graph.graph.node_count(),
-        graph.graph.edge_count()
+        analysis.graph.graph.node_count(),
+        analysis.graph.graph.edge_count()
+    );
+    eprintln!(
+        "Excluded commits: {} after end date, {} ignored revs, {} not selected, {} by message",
+        excluded.after_end, excluded.ignored_revs, excluded.unselected, excluded.by_message
+    );
+    match excluded.stopped {
+        Some(Stop::StartDate) => eprintln!("Stopped the walk at the start date"),
+        Some(Stop::MaxCommits) => eprintln!(
+            "Stopped the walk after --max-commits {}",
+            analysis.filter.max_commits.unwrap_or_default()
+        ),
+        None => {}
+    }
+    for (rule, count) in &excluded.identities {
+        eprintln!("Excluded commits with {}: {}", rule, count);
+    }
+    let action = match options.oversized {
+        Oversized::Drop => "dropped",
+        Oversized::Keep => "kept",
+        Oversized::DownWeight => "down-weighted",
+    };
+    eprintln!(
+        "Oversized changesets (>= {} files): {} {}",
+        options.changeset_limit, analysis.oversized, action
     );
 }
+
+#[cfg(test)]
+use clap::ErrorKind;
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use tempfile::TempDir;
+
+/// A repo with a commit for each list of files, every file written anew.
+#[cfg(test)]
+fn repo_with_commits(commits: &[&[&str]]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    let repo = git2::Repository::init(dir.path()).unwrap();
+    let signature = git2::Signature::now("Tester", "tester@example.com").unwrap();
+    for (n, files) in commits.iter().enumerate() {
+        let mut index = repo.index().unwrap();
+        for file in *files {
+            fs::write(dir.path().join(file), n.to_string()).unwrap();
+            index.add_path(Path::new(file)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "change",
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    }
+    dir
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<ArgMatches<'static>, clap::Error> {
+    app().get_matches_from_safe(std::iter::once("rorqual").chain(args.iter().copied()))
+}
+
+#[test]
+fn test_subcommands() {
+    let matches = parse(&["neighbours", "a", "b", "--depth", "2", "--repo", "x"]).unwrap();
+    assert_eq!(matches.value_of("repo"), Some("x"));
+    let (name, args) = matches.subcommand();
+    assert_eq!(name, "neighbours");
+    let paths: Vec<&str> = args.unwrap().values_of("paths").unwrap().collect();
+    assert_eq!(paths, vec!["a", "b"]);
+
+    let matches = parse(&["compare", "v1", "v2", "--scans"]).unwrap();
+    let args = matches.subcommand_matches("compare").unwrap();
+    assert_eq!(args.value_of("old"), Some("v1"));
+    assert!(args.is_present("scans"));
+
+    // Every subcommand only takes its own options.
+    assert!(parse(&["report", "--top-k", "3"]).is_err());
+    assert!(parse(&["scan", "--format", "dot"]).is_err());
+    assert!(parse(&["export", "--format", "csv"]).is_err());
+    assert!(parse(&["compare", "v1", "v2", "--revisions", "HEAD"]).is_err());
+    assert_eq!(
+        parse(&[]).unwrap_err().kind,
+        ErrorKind::MissingArgumentOrSubcommand
+    );
+}
+
+#[test]
+fn test_default_selection_matches_library() {
+    let matches = parse(&["report"]).unwrap();
+    let args = matches.subcommand_matches("report").unwrap();
+    let filter = git_filter(args, Path::new(".")).unwrap();
+    assert!(matches!(filter.selection, CommitSelection::All));
+    assert!(matches!(
+        GitFilter::default().selection,
+        CommitSelection::All
+    ));
+}
+
+#[test]
+fn test_scan_options_conflict_with_saved_scans() {
+    for subcommand in &["report", "neighbours", "export"] {
+        let mut args = vec![*subcommand];
+        if *subcommand == "neighbours" {
+            args.push("a");
+        }
+        args.extend(&["--scan", "saved"]);
+        assert!(parse(&args).is_ok(), "{:?}", args);
+        for option in &[
+            "--start-time=90d",
+            "--commits=merges",
+            "--merge-diff=merge-base",
+        ] {
+            let mut args = args.clone();
+            args.push(option);
+            assert_eq!(
+                parse(&args).unwrap_err().kind,
+                ErrorKind::ArgumentConflict,
+                "{:?}",
+                args
+            );
+        }
+    }
+    assert_eq!(
+        parse(&["compare", "a", "b", "--scans", "--max-commits", "3"])
+            .unwrap_err()
+            .kind,
+        ErrorKind::ArgumentConflict
+    );
+    assert_eq!(
+        parse(&["scan", "--update", "-o", "saved", "--include", "src"])
+            .unwrap_err()
+            .kind,
+        ErrorKind::ArgumentConflict
+    );
+    assert_eq!(
+        parse(&["scan", "--update"]).unwrap_err().kind,
+        ErrorKind::MissingRequiredArgument
+    );
+    // Graph options still apply to a saved scan.
+    assert!(parse(&["report", "--scan", "saved", "--weighting", "count"]).is_ok());
+}
+
+#[test]
+fn test_output() {
+    let repo = repo_with_commits(&[&["a", "b"], &["b", "c"]]);
+    let out = TempDir::new().unwrap();
+    let saved = out.path().join("saved");
+    let report = out.path().join("report.csv");
+    let repo_path = repo.path().to_str().unwrap();
+
+    let matches = parse(&["scan", "--repo", repo_path, "-o", saved.to_str().unwrap()]).unwrap();
+    run(&matches, false).unwrap();
+    assert!(saved.exists());
+
+    let matches = parse(&[
+        "report",
+        "--repo",
+        repo_path,
+        "-o",
+        report.to_str().unwrap(),
+    ])
+    .unwrap();
+    run(&matches, false).unwrap();
+    let walked = fs::read_to_string(&report).unwrap();
+    let mut files: Vec<&str> = walked
+        .lines()
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    files.sort_unstable();
+    assert_eq!(files, vec!["\"a\"", "\"b\"", "\"c\""]);
+
+    let matches = parse(&[
+        "report",
+        "--scan",
+        saved.to_str().unwrap(),
+        "-o",
+        report.to_str().unwrap(),
+    ])
+    .unwrap();
+    run(&matches, false).unwrap();
+    assert_eq!(fs::read_to_string(&report).unwrap(), walked);
+}
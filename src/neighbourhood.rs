@@ -0,0 +1,181 @@
+use petgraph::{
+    graph::{EdgeIndex, NodeIndex},
+    visit::EdgeRef,
+};
+use std::collections::HashSet;
+
+use super::git_graph::{GitGraph, Similarity, Weighting};
+
+/// Which edges count when walking and printing a neighbourhood.
+pub struct NeighbourQuery {
+    pub weighting: Weighting,
+    /// Edges lighter than this are ignored.
+    pub min_weight: f64,
+    /// Only follow the heaviest edges of every file.
+    pub top_k: Option<usize>,
+}
+
+impl NeighbourQuery {
+    /// The files at most `depth` hops away from any of `starts`.
+    pub fn neighbourhood(
+        &self,
+        graph: &GitGraph,
+        starts: &[NodeIndex],
+        depth: u64,
+    ) -> HashSet<NodeIndex> {
+        // Level by level, so every file is expanded once, at its fewest hops.
+        let mut reached: HashSet<NodeIndex> = starts.iter().copied().collect();
+        let mut level: Vec<NodeIndex> = reached.iter().copied().collect();
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for idx in level {
+                for (_, neighbour) in self.edges(graph, idx) {
+                    if reached.insert(neighbour) {
+                        next.push(neighbour);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+        reached
+    }
+
+    pub fn keeps(&self, graph: &GitGraph, edge: EdgeIndex) -> bool {
+        graph.similarity(edge, self.weighting).0 >= self.min_weight
+    }
+
+    /// The kept edges of `idx` that are followed into the neighbourhood with
+    /// the neighbour they lead to, the heaviest first and at most `top_k` of
+    /// them.
+    pub fn edges(&self, graph: &GitGraph, idx: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        let mut edges: Vec<(Similarity, EdgeIndex, NodeIndex)> = graph
+            .graph
+            .edges(idx)
+            .filter(|edge| self.keeps(graph, edge.id()))
+            .map(|edge| {
+                let weight = graph.similarity(edge.id(), self.weighting);
+                (weight, edge.id(), edge.target())
+            })
+            .collect();
+        edges.sort_by(|(a, _, a_idx), (b, _, b_idx)| {
+            b.partial_cmp(a).unwrap().then(a_idx.cmp(b_idx))
+        });
+        edges.truncate(self.top_k.unwrap_or(edges.len()));
+        edges
+            .into_iter()
+            .map(|(_, edge, idx)| (edge, idx))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+use super::git_graph::{build_graph, ChangeSet, GitFile, GraphOptions, Status};
+
+#[cfg(test)]
+fn names(graph: &GitGraph, nodes: HashSet<NodeIndex>) -> Vec<&str> {
+    let mut names: Vec<&str> = nodes
+        .into_iter()
+        .map(|idx| graph.graph[idx].name.as_str())
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[test]
+fn test_neighbourhood() {
+    // a - b - c - d with a - b twice as heavy, and e - b once
+    let changesets = [
+        &["a", "b"][..],
+        &["a", "b"],
+        &["b", "c"],
+        &["c", "d"],
+        &["b", "e"],
+    ]
+    .iter()
+    .map(|names| ChangeSet {
+        time: 0,
+        files: names
+            .iter()
+            .map(|name| GitFile {
+                status: Status::Modified,
+                name: name.to_string(),
+            })
+            .collect(),
+    })
+    .collect();
+    let graph = build_graph(changesets, &GraphOptions::default());
+    let a = graph.node("a").unwrap();
+    let d = graph.node("d").unwrap();
+    let query = NeighbourQuery {
+        weighting: Weighting::Count,
+        min_weight: 0.0,
+        top_k: None,
+    };
+
+    assert_eq!(names(&graph, query.neighbourhood(&graph, &[a], 0)), ["a"]);
+    assert_eq!(
+        names(&graph, query.neighbourhood(&graph, &[a], 2)),
+        ["a", "b", "c", "e"]
+    );
+    assert_eq!(
+        names(&graph, query.neighbourhood(&graph, &[a, d], 1)),
+        ["a", "b", "c", "d"]
+    );
+
+    let heavy = NeighbourQuery {
+        min_weight: 2.0,
+        ..query
+    };
+    assert_eq!(
+        names(&graph, heavy.neighbourhood(&graph, &[a], 3)),
+        ["a", "b"]
+    );
+
+    let top = NeighbourQuery {
+        weighting: Weighting::Count,
+        min_weight: 0.0,
+        top_k: Some(1),
+    };
+    assert_eq!(
+        names(&graph, top.neighbourhood(&graph, &[a], 3)),
+        ["a", "b"]
+    );
+    let b = graph.node("b").unwrap();
+    assert_eq!(
+        top.edges(&graph, b),
+        [(graph.graph.find_edge(a, b).unwrap(), a)]
+    );
+    assert_eq!(query.edges(&graph, b).len(), 3);
+}
+
+#[test]
+fn test_neighbourhood_of_long_chain() {
+    let changesets = (0..50_000)
+        .map(|i| ChangeSet {
+            time: 0,
+            files: [i, i + 1]
+                .iter()
+                .map(|n| GitFile {
+                    status: Status::Modified,
+                    name: n.to_string(),
+                })
+                .collect(),
+        })
+        .collect();
+    let graph = build_graph(changesets, &GraphOptions::default());
+    let query = NeighbourQuery {
+        weighting: Weighting::Count,
+        min_weight: 0.0,
+        top_k: None,
+    };
+    let start = graph.node("0").unwrap();
+
+    assert_eq!(
+        query.neighbourhood(&graph, &[start], u64::MAX).len(),
+        50_001
+    );
+    assert_eq!(query.neighbourhood(&graph, &[start], 100).len(), 101);
+}
@@ -0,0 +1,204 @@
+use regex::RegexSet;
+use std::{fs, path::Path};
+
+use super::error::RorqualError;
+
+/// Which files make it into the graph.
+///
+/// Patterns are gitignore style globs, or regexes when prefixed with `re:`.
+/// A file is kept if it matches any include pattern, or there are none, and
+/// no exclude pattern.
+#[derive(Clone, Debug)]
+pub struct PathFilter {
+    include: RegexSet,
+    exclude: RegexSet,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        PathFilter {
+            include: RegexSet::empty(),
+            exclude: RegexSet::empty(),
+        }
+    }
+}
+
+impl PathFilter {
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<Self, RorqualError> {
+        Ok(PathFilter {
+            include: pattern_set(include)?,
+            exclude: pattern_set(exclude)?,
+        })
+    }
+
+    /// Reads patterns from a file, one per line. Lines are exclude patterns
+    /// unless they start with `+`, blank lines and `#` comments are skipped.
+    pub fn from_file(path: &Path) -> Result<Self, RorqualError> {
+        PathFilter::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads patterns in the format of [`PathFilter::from_file`].
+    pub fn parse(content: &str) -> Result<Self, RorqualError> {
+        let (include, exclude) = read_patterns(content);
+        PathFilter::new(&include, &exclude)
+    }
+
+    /// Adds the patterns of `other` to the ones of `self`.
+    pub fn extend(self, other: PathFilter) -> Self {
+        let join = |a: RegexSet, b: RegexSet| {
+            RegexSet::new(a.patterns().iter().chain(b.patterns())).unwrap()
+        };
+        PathFilter {
+            include: join(self.include, other.include),
+            exclude: join(self.exclude, other.exclude),
+        }
+    }
+
+    /// The regexes of the include and the exclude patterns.
+    pub fn patterns(&self) -> (&[String], &[String]) {
+        (self.include.patterns(), self.exclude.patterns())
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+fn read_patterns(content: &str) -> (Vec<&str>, Vec<&str>) {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.strip_prefix('+') {
+            Some(pattern) => include.push(pattern.trim_start()),
+            None => exclude.push(line),
+        }
+    }
+    (include, exclude)
+}
+
+fn pattern_set<S: AsRef<str>>(patterns: &[S]) -> Result<RegexSet, RorqualError> {
+    let regexes = patterns.iter().map(|pattern| {
+        let pattern = pattern.as_ref();
+        match pattern.strip_prefix("re:") {
+            Some(regex) => regex.to_string(),
+            None => glob_to_regex(pattern, true),
+        }
+    });
+    Ok(RegexSet::new(regexes)?)
+}
+
+/// Translates a gitignore style glob into a regex over paths relative to the
+/// repo root. With `match_below` a glob that names a directory matches every
+/// file below it, as in .gitignore but unlike .gitattributes.
+pub fn glob_to_regex(glob: &str, match_below: bool) -> String {
+    let directory_only = glob.ends_with('/');
+    let glob = glob.trim_end_matches('/');
+    // Without a slash in front or in the middle a glob matches at any depth.
+    let anchored = glob.contains('/');
+    let glob = glob.trim_start_matches('/');
+
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    regex.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push_str(match (directory_only, match_below) {
+        (true, _) => "/",
+        (false, true) => "(?:/|$)",
+        (false, false) => "$",
+    });
+    regex
+}
+
+#[cfg(test)]
+fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+    PathFilter::new(include, exclude).unwrap()
+}
+
+#[test]
+fn test_globs() {
+    let matches = |glob: &str, path: &str| filter(&[glob], &[]).matches(path);
+
+    assert!(matches("*.lock", "Cargo.lock"));
+    assert!(matches("*.lock", "web/yarn.lock"));
+    assert!(!matches("*.lock", "Cargo.lock.bak"));
+    assert!(matches("/src", "src/main.rs"));
+    assert!(!matches("/src", "web/src/app.js"));
+    assert!(matches("src", "web/src/app.js"));
+    assert!(matches("docs/*.md", "docs/index.md"));
+    assert!(!matches("docs/*.md", "docs/api/index.md"));
+    assert!(!matches("docs/*.md", "web/docs/index.md"));
+    assert!(matches("docs/**/*.md", "docs/api/index.md"));
+    assert!(matches("**/fixtures/", "tests/fixtures/repo/a"));
+    assert!(!matches("fixtures/", "tests/fixtures"));
+    assert!(matches("file?.[ch]", "lib/file1.c"));
+    assert!(!matches("file[!0-9].c", "lib/file1.c"));
+    assert!(matches("a+b.txt", "a+b.txt"));
+}
+
+#[test]
+fn test_exclude_wins() {
+    let filter = filter(&["src/", "re:\\.toml$"], &["*_test.rs", "re:^Cargo"]);
+
+    assert!(filter.matches("src/main.rs"));
+    assert!(filter.matches("config/rorqual.toml"));
+    assert!(!filter.matches("src/main_test.rs"));
+    assert!(!filter.matches("Cargo.toml"));
+    assert!(!filter.matches("README.md"));
+    assert!(PathFilter::default().matches("README.md"));
+}
+
+#[test]
+fn test_filter_file() {
+    let content = "# generated\n*.lock\n\n+ src/\n+re:^lib/\n  vendor/  \n";
+    let (include, exclude) = read_patterns(content);
+
+    assert_eq!(include, ["src/", "re:^lib/"]);
+    assert_eq!(exclude, ["*.lock", "vendor/"]);
+}
+
+#[test]
+fn test_invalid_regex() {
+    assert!(matches!(
+        PathFilter::new(&["re:("], &[]),
+        Err(RorqualError::InvalidRegex(_))
+    ));
+}
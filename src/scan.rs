@@ -0,0 +1,605 @@
+use chrono::{DateTime, Utc};
+use git2::Oid;
+use petgraph::graph::NodeIndex;
+use regex::{Regex, RegexSet};
+use rustc_hash::FxHashMap;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+};
+
+use super::analyser::centrality::{betweenness_centrality, betweenness_centrality_unweighted};
+use super::error::RorqualError;
+use super::git2graph::{
+    extends, repo_to_changesets, walk_history, CommitInfo, CommitSelection, Exclusions, GitFilter,
+    History, IdentityRule, MergeDiff,
+};
+use super::git_graph::{
+    build_graph, ChangeSet, Conversion, GitFile, GitGraph, GraphOptions, Oversized, RenameMap,
+    Weighting,
+};
+use super::neighbourhood::NeighbourQuery;
+use super::path_filter::PathFilter;
+
+/// Walks a repository and builds the co-change graph of its files.
+///
+/// Every setting starts out as in [`GitFilter::default`] and
+/// [`GraphOptions::default`].
+pub struct Scan {
+    path: PathBuf,
+    filter: GitFilter,
+    options: GraphOptions,
+}
+
+impl Scan {
+    /// A scan of the repository `path` is in.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Scan {
+            path: path.into(),
+            filter: GitFilter::default(),
+            options: GraphOptions::default(),
+        }
+    }
+
+    /// Replaces all history and path settings at once.
+    pub fn filter(mut self, filter: GitFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Stops the walk at the first commit made at or before `date`.
+    pub fn since(mut self, date: DateTime<Utc>) -> Self {
+        self.filter.start_date = Some(date);
+        self
+    }
+
+    /// Leaves out the commits newer than `date`.
+    pub fn until(mut self, date: DateTime<Utc>) -> Self {
+        self.filter.end_date = Some(date);
+        self
+    }
+
+    /// Walks a revision, range or ref glob instead of HEAD, can be repeated.
+    pub fn revision<S: Into<String>>(mut self, spec: S) -> Self {
+        self.filter.revisions.push(spec.into());
+        self
+    }
+
+    /// Which commits become changesets.
+    pub fn selection(mut self, selection: CommitSelection) -> Self {
+        self.filter.selection = selection;
+        self
+    }
+
+    /// What merge commits are diffed against.
+    pub fn merge_diff(mut self, merge_diff: MergeDiff) -> Self {
+        self.filter.merge_diff = merge_diff;
+        self
+    }
+
+    /// Stops the walk after `max` selected commits.
+    pub fn max_commits(mut self, max: u32) -> Self {
+        self.filter.max_commits = Some(max);
+        self
+    }
+
+    /// Only keeps the files `path_filter` lets through.
+    pub fn path_filter(mut self, path_filter: PathFilter) -> Self {
+        self.filter.path_filter = path_filter;
+        self
+    }
+
+    /// Whether generated, vendored and `.rorqualignore`d files are left out.
+    pub fn skip_ignored(mut self, skip: bool) -> Self {
+        self.filter.skip_ignored = skip;
+        self
+    }
+
+    /// Keeps or leaves out commits by their author or committer, can be
+    /// repeated.
+    pub fn identity(mut self, rule: IdentityRule) -> Self {
+        self.filter.identities.push(rule);
+        self
+    }
+
+    /// Leaves out the commits whose message matches one of `patterns`.
+    pub fn message_excludes(mut self, patterns: RegexSet) -> Self {
+        self.filter.message_excludes = patterns;
+        self
+    }
+
+    /// Groups the commits whose message has the same ticket ID.
+    pub fn ticket_pattern(mut self, pattern: Regex) -> Self {
+        self.filter.ticket_pattern = Some(pattern);
+        self
+    }
+
+    /// Groups consecutive commits of an author at most `seconds` apart.
+    pub fn author_window(mut self, seconds: i64) -> Self {
+        self.filter.author_window = Some(seconds);
+        self
+    }
+
+    /// Leaves out the commits listed in `file` instead of the ones in
+    /// `.git-blame-ignore-revs`.
+    pub fn ignore_revs_file<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.filter.ignore_revs_file = Some(file.into());
+        self
+    }
+
+    /// Changesets of at least `limit` files are oversized.
+    pub fn changeset_limit(mut self, limit: usize) -> Self {
+        self.options.changeset_limit = limit;
+        self
+    }
+
+    /// What happens to oversized changesets.
+    pub fn oversized(mut self, oversized: Oversized) -> Self {
+        self.options.oversized = oversized;
+        self
+    }
+
+    /// Walks the history and builds the graph.
+    pub fn run(self) -> Result<Analysis, RorqualError> {
+        let history = repo_to_changesets(self.path.clone(), &self.filter)?;
+        let oversized = history
+            .changesets
+            .iter()
+            .filter(|set| self.options.is_oversized(set))
+            .count();
+        let mut graph = build_graph(history.changesets.clone(), &self.options);
+        graph.fold_renames(&history.renames);
+        Ok(Analysis {
+            graph,
+            changesets: history.changesets,
+            renames: history.renames,
+            commits: history.commits,
+            tips: history.tips,
+            excluded: history.excluded,
+            oversized,
+            filter: self.filter,
+            options: self.options,
+        })
+    }
+}
+
+/// The graph of a [`Scan`] together with what it was built from and what it
+/// left out.
+pub struct Analysis {
+    pub graph: GitGraph,
+    /// The changesets of the graph, newest first, oversized ones included.
+    pub changesets: Vec<ChangeSet>,
+    pub renames: RenameMap,
+    pub commits: Vec<CommitInfo>,
+    /// The commits the walk started from.
+    pub tips: Vec<Oid>,
+    pub excluded: Exclusions,
+    /// Changesets at or over the changeset limit.
+    pub oversized: usize,
+    pub filter: GitFilter,
+    pub options: GraphOptions,
+}
+
+/// How [`Analysis::update`] brought an analysis up to date.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// This many new commits were walked and merged in.
+    Merged(usize),
+    /// The whole history was scanned again, for this reason.
+    Rescanned(&'static str),
+}
+
+impl Analysis {
+    /// Normalised betweenness centrality of every file, over the weights
+    /// turned into distances or, without a conversion, counting hops.
+    pub fn centrality(
+        &self,
+        distance: Option<(Weighting, Conversion)>,
+    ) -> FxHashMap<NodeIndex, f64> {
+        match distance {
+            Some((weighting, conversion)) => {
+                betweenness_centrality(&self.graph.distances(weighting, conversion))
+            }
+            None => betweenness_centrality_unweighted(&self.graph.graph),
+        }
+    }
+
+    /// Brings the analysis up to date with the repo at `path` with the same
+    /// settings. Only the commits added since are walked and merged into the
+    /// graph, unless the history was rewritten or the settings don't allow
+    /// it, then the whole history is scanned again.
+    pub fn update<P: Into<PathBuf>>(self, path: P) -> Result<(Analysis, Update), RorqualError> {
+        let path = path.into();
+        let rescan = if self.filter.ticket_pattern.is_some() || self.filter.author_window.is_some()
+        {
+            // New commits could belong to changesets that were already built.
+            Some("changesets are grouped across commits")
+        } else if self.filter.max_commits.is_some() {
+            Some("the number of commits is limited")
+        } else if !extends(&path, &self.filter.revisions, &self.tips)? {
+            Some("history was rewritten")
+        } else {
+            None
+        };
+        if let Some(reason) = rescan {
+            let scan = Scan {
+                path,
+                filter: self.filter,
+                options: self.options,
+            };
+            return Ok((scan.run()?, Update::Rescanned(reason)));
+        }
+
+        let mut filter = self.filter.clone();
+        filter
+            .revisions
+            .extend(self.tips.iter().map(|tip| format!("^{}", tip)));
+        let history = walk_history(path, &filter)?;
+        let added = history.commits.len();
+        Ok((self.merge(history), Update::Merged(added)))
+    }
+
+    /// Adds the changesets of the newer `history`.
+    fn merge(self, history: History) -> Analysis {
+        let Analysis {
+            graph,
+            changesets,
+            renames,
+            commits,
+            excluded,
+            oversized,
+            filter,
+            options,
+            ..
+        } = self;
+
+        // Files renamed since get the changes before under their new path,
+        // also in the stored changesets so later updates continue from it.
+        let current = |name: &String| history.renames.get(name).unwrap_or(name).clone();
+        let renamed = changesets
+            .iter()
+            .flat_map(|set| &set.files)
+            .any(|file| history.renames.contains_key(&file.name));
+        let (changesets, old_graph) = if renamed {
+            let changesets: Vec<ChangeSet> = changesets
+                .into_iter()
+                .map(|set| ChangeSet {
+                    time: set.time,
+                    files: set
+                        .files
+                        .into_iter()
+                        .map(|file| GitFile {
+                            status: file.status,
+                            name: current(&file.name),
+                        })
+                        .collect(),
+                })
+                .collect();
+            let old_graph = build_graph(changesets.clone(), &options);
+            (changesets, old_graph)
+        } else {
+            (changesets, without_renames(graph))
+        };
+        let mut renames: RenameMap = renames
+            .iter()
+            .map(|(old, name)| (old.clone(), current(name)))
+            .collect();
+        renames.extend(history.renames);
+
+        let new_graph = build_graph(history.changesets.clone(), &options);
+        let mut graph = new_graph.merge(old_graph);
+        graph.fold_renames(&renames);
+
+        let mut excluded = excluded;
+        let new = history.excluded;
+        excluded.after_end += new.after_end;
+        excluded.unselected += new.unselected;
+        excluded.by_message += new.by_message;
+        excluded.ignored_revs += new.ignored_revs;
+        for (rule, count) in new.identities {
+            *excluded.identities.entry(rule).or_insert(0) += count;
+        }
+        excluded.stopped = excluded.stopped.or(new.stopped);
+
+        Analysis {
+            graph,
+            oversized: oversized
+                + history
+                    .changesets
+                    .iter()
+                    .filter(|set| options.is_oversized(set))
+                    .count(),
+            changesets: history.changesets.into_iter().chain(changesets).collect(),
+            renames,
+            commits: history.commits.into_iter().chain(commits).collect(),
+            tips: history.tips,
+            excluded,
+            filter,
+            options,
+        }
+    }
+
+    /// The files at most `depth` hops away from any of `paths`.
+    pub fn neighbourhood<S: AsRef<str>>(
+        &self,
+        paths: &[S],
+        depth: u64,
+        query: &NeighbourQuery,
+    ) -> Result<HashSet<NodeIndex>, RorqualError> {
+        let starts = paths
+            .iter()
+            .map(|path| self.graph.node(path.as_ref()))
+            .collect::<Result<Vec<NodeIndex>, RorqualError>>()?;
+        Ok(query.neighbourhood(&self.graph, &starts, depth))
+    }
+}
+
+/// Drops the historic paths [`GitGraph::fold_renames`] added, so that they
+/// can't shadow files of another graph.
+fn without_renames(mut graph: GitGraph) -> GitGraph {
+    graph.name_table = graph
+        .graph
+        .node_indices()
+        .map(|idx| (graph.graph[idx].name.clone(), idx))
+        .collect();
+    graph
+}
+
+/// The centrality of a file in two analyses, `None` where it is not part of
+/// the graph.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CentralityChange {
+    pub path: String,
+    pub old: Option<f64>,
+    pub new: Option<f64>,
+}
+
+impl CentralityChange {
+    pub fn delta(&self) -> f64 {
+        self.new.unwrap_or(0.0) - self.old.unwrap_or(0.0)
+    }
+}
+
+/// The centrality of every file of either analysis, the largest changes
+/// first and ties by path.
+pub fn compare(
+    old: &Analysis,
+    new: &Analysis,
+    distance: Option<(Weighting, Conversion)>,
+) -> Vec<CentralityChange> {
+    let mut paths: BTreeMap<String, (Option<f64>, Option<f64>)> = BTreeMap::new();
+    for (idx, betweenness) in old.centrality(distance) {
+        paths
+            .entry(old.graph.graph[idx].name.clone())
+            .or_default()
+            .0 = Some(betweenness);
+    }
+    for (idx, betweenness) in new.centrality(distance) {
+        paths
+            .entry(new.graph.graph[idx].name.clone())
+            .or_default()
+            .1 = Some(betweenness);
+    }
+    let mut changes: Vec<CentralityChange> = paths
+        .into_iter()
+        .map(|(path, (old, new))| CentralityChange { path, old, new })
+        .collect();
+    changes.sort_by(|a, b| b.delta().abs().total_cmp(&a.delta().abs()));
+    changes
+}
+
+#[cfg(test)]
+use super::git2graph::load_fixture_repo;
+
+#[test]
+fn test_scan() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let analysis = Scan::new(dir.path())
+        .revision("v1.1")
+        .selection(CommitSelection::NonMerges)
+        .changeset_limit(3)
+        .run()
+        .unwrap();
+
+    // "init" touched a, b and c at once
+    assert_eq!(analysis.oversized, 1);
+    assert_eq!(analysis.excluded.unselected, 1);
+    assert_eq!(analysis.graph.graph.node_count(), 5);
+
+    let centrality = analysis.centrality(Some((Weighting::Count, Conversion::MaxMinus)));
+    assert_eq!(centrality.len(), 5);
+    let query = NeighbourQuery {
+        weighting: Weighting::Count,
+        min_weight: 0.0,
+        top_k: None,
+    };
+    assert_eq!(analysis.neighbourhood(&["c"], 1, &query).unwrap().len(), 2);
+    assert!(matches!(
+        analysis.neighbourhood(&["z"], 1, &query),
+        Err(RorqualError::UnknownPath(_))
+    ));
+}
+
+#[test]
+fn test_compare() {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    let scan = |revision: &str| {
+        Scan::new(dir.path())
+            .revision(revision)
+            .selection(CommitSelection::NonMerges)
+            .run()
+            .unwrap()
+    };
+    let changes = compare(&scan("v1.0"), &scan("v1.1"), None);
+
+    // v1.1 adds e, which only the squashed commit touched
+    let e = changes.iter().find(|change| change.path == "e").unwrap();
+    assert_eq!((e.old, e.new), (None, Some(0.0)));
+    assert_eq!(changes.len(), 5);
+    assert!(changes
+        .windows(2)
+        .all(|pair| pair[0].delta().abs() >= pair[1].delta().abs()));
+}
+
+#[cfg(test)]
+use git2::{Repository, Signature, Time};
+
+/// Commits on top of HEAD, or of its parent with `amend`, the files written
+/// and removed in HEAD's tree.
+#[cfg(test)]
+fn commit(repo: &Repository, files: &[(&str, &str)], removed: &[&str], amend: bool) -> Oid {
+    let mut parent = repo.head().unwrap().peel_to_commit().unwrap();
+    if amend {
+        parent = parent.parent(0).unwrap();
+    }
+    let mut tree = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+    for (name, content) in files {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        tree.insert(name, blob, 0o100644).unwrap();
+    }
+    for name in removed {
+        tree.remove(name).unwrap();
+    }
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+    let time = Time::new(parent.time().seconds() + 60, 0);
+    let signature = Signature::new("John Doe", "john@doe.org", &time).unwrap();
+    let id = repo
+        .commit(None, &signature, &signature, "change", &tree, &[&parent])
+        .unwrap();
+    repo.head().unwrap().set_target(id, "test").unwrap();
+    id
+}
+
+/// The co-change count of every pair of files, by path.
+#[cfg(test)]
+fn co_changes(analysis: &Analysis) -> BTreeMap<(String, String), u32> {
+    let graph = &analysis.graph.graph;
+    graph
+        .edge_indices()
+        .map(|edge| {
+            let (a, b) = graph.edge_endpoints(edge).unwrap();
+            let mut pair = [graph[a].name.clone(), graph[b].name.clone()];
+            pair.sort();
+            let [a, b] = pair;
+            ((a, b), graph[edge].count)
+        })
+        .collect()
+}
+
+#[test]
+fn test_update_merges_new_commits() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let scan = || {
+        Scan::new(dir.path())
+            .selection(CommitSelection::NonMerges)
+            .run()
+            .unwrap()
+    };
+    let analysis = scan();
+    commit(&repo, &[("a", "new a"), ("f", "f")], &[], false);
+    let tip = commit(&repo, &[("b", "new b"), ("f", "new f")], &[], false);
+
+    let (updated, update) = analysis.update(dir.path()).unwrap();
+    let rescanned = scan();
+
+    assert_eq!(update, Update::Merged(2));
+    assert_eq!(updated.tips, [tip]);
+    assert_eq!(updated.commits, rescanned.commits);
+    assert_eq!(updated.changesets.len(), rescanned.changesets.len());
+    assert_eq!(updated.excluded, rescanned.excluded);
+    assert_eq!(co_changes(&updated), co_changes(&rescanned));
+
+    let (unchanged, update) = updated.update(dir.path()).unwrap();
+    assert_eq!(update, Update::Merged(0));
+    assert_eq!(co_changes(&unchanged), co_changes(&rescanned));
+}
+
+#[test]
+fn test_update_follows_renames() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let scan = || {
+        Scan::new(dir.path())
+            .selection(CommitSelection::NonMerges)
+            .run()
+            .unwrap()
+    };
+    let analysis = scan();
+    let content = repo
+        .find_blob(
+            repo.head()
+                .unwrap()
+                .peel_to_tree()
+                .unwrap()
+                .get_name("c")
+                .unwrap()
+                .id(),
+        )
+        .unwrap()
+        .content()
+        .to_vec();
+    commit(
+        &repo,
+        &[("g", std::str::from_utf8(&content).unwrap())],
+        &["c"],
+        false,
+    );
+
+    let (updated, update) = analysis.update(dir.path()).unwrap();
+    let rescanned = scan();
+
+    assert_eq!(update, Update::Merged(1));
+    assert!(updated.graph.name_table.contains_key("g"));
+    assert_eq!(
+        updated.graph.node("c").unwrap(),
+        updated.graph.node("g").unwrap()
+    );
+    assert_eq!(co_changes(&updated), co_changes(&rescanned));
+}
+
+#[test]
+fn test_update_follows_chained_renames() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let scan = || {
+        Scan::new(dir.path())
+            .selection(CommitSelection::NonMerges)
+            .run()
+            .unwrap()
+    };
+    let rename = |from: &str, to: &str| {
+        let tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let blob = repo.find_blob(tree.get_name(from).unwrap().id()).unwrap();
+        let content = std::str::from_utf8(blob.content()).unwrap().to_string();
+        commit(&repo, &[(to, &content)], &[from], false);
+    };
+
+    let analysis = scan();
+    rename("c", "g");
+    let (analysis, _) = analysis.update(dir.path()).unwrap();
+    rename("g", "h");
+    let (updated, update) = analysis.update(dir.path()).unwrap();
+    let rescanned = scan();
+
+    assert_eq!(update, Update::Merged(1));
+    assert!(updated
+        .changesets
+        .iter()
+        .flat_map(|set| &set.files)
+        .all(|file| file.name != "c" && file.name != "g"));
+    assert_eq!(updated.renames.get("c").unwrap(), "h");
+    assert_eq!(co_changes(&updated), co_changes(&rescanned));
+}
+
+#[test]
+fn test_update_detects_rewrites() {
+    let (dir, repo) = load_fixture_repo("merge-repo").unwrap();
+    let analysis = Scan::new(dir.path())
+        .selection(CommitSelection::NonMerges)
+        .run()
+        .unwrap();
+    commit(&repo, &[("a", "rewritten a")], &[], true);
+
+    let (updated, update) = analysis.update(dir.path()).unwrap();
+
+    assert_eq!(update, Update::Rescanned("history was rewritten"));
+    assert_eq!(updated.tips, [repo.head().unwrap().target().unwrap()]);
+}
@@ -0,0 +1,710 @@
+//! The file `rorqual scan` saves an [`Analysis`] to, so later commands don't
+//! have to walk the history again.
+//!
+//! A file starts with [`MAGIC`] and [`FORMAT_VERSION`] as little endian
+//! `u32`. Then follow a table of every path, the settings of the scan, the
+//! exclusions, the commits and the tips of the walk, the changesets, the
+//! renames and the graph. Counts,
+//! indices and times are LEB128 varints, zigzag encoded where they can be
+//! negative, weights little endian `f64`s and paths indices into the table.
+use chrono::{TimeZone, Utc};
+use git2::Oid;
+use petgraph::{graph::NodeIndex, visit::EdgeRef};
+use regex::{Regex, RegexSet};
+use rustc_hash::FxHashMap;
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+use super::error::RorqualError;
+use super::git2graph::{
+    CommitInfo, CommitSelection, Exclusions, GitFilter, IdentityRule, MergeDiff, Role, Stop,
+};
+use super::git_graph::{
+    ChangeSet, CoChange, FileNode, GitFile, GitGraph, GraphOptions, Oversized, RenameMap, Status,
+};
+use super::path_filter::PathFilter;
+use super::scan::Analysis;
+
+pub const MAGIC: &[u8; 8] = b"RORQUAL\0";
+/// Bumped whenever the layout changes, files in any other version are
+/// rejected.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Saves the analysis to `path`. It is written to a temporary file next to
+/// it first, so a failed write leaves a scan saved there before intact.
+pub fn save(analysis: &Analysis, path: &Path) -> Result<(), RorqualError> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut writer = BufWriter::new(NamedTempFile::new_in(directory)?);
+    writer.write_all(&encode(analysis))?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Analysis, RorqualError> {
+    decode(&fs::read(path)?).map_err(|e| match e {
+        Invalid::Version(version) => RorqualError::IncompatibleScan {
+            path: path.to_path_buf(),
+            version,
+        },
+        Invalid::Data(reason) => RorqualError::InvalidScan {
+            path: path.to_path_buf(),
+            reason,
+        },
+    })
+}
+
+/// Why bytes can't be decoded into an [`Analysis`].
+#[derive(Debug, PartialEq)]
+enum Invalid {
+    Version(u32),
+    Data(String),
+}
+
+fn encode(analysis: &Analysis) -> Vec<u8> {
+    let mut body = Encoder::default();
+
+    body.uint(analysis.options.changeset_limit as u64);
+    body.bytes.push(match analysis.options.oversized {
+        Oversized::Drop => 0,
+        Oversized::Keep => 1,
+        Oversized::DownWeight => 2,
+    });
+    body.uint(analysis.oversized as u64);
+    encode_filter(&mut body, &analysis.filter);
+
+    let excluded = &analysis.excluded;
+    for count in [
+        excluded.after_end,
+        excluded.unselected,
+        excluded.by_message,
+        excluded.ignored_revs,
+    ] {
+        body.uint(count as u64);
+    }
+    body.uint(excluded.identities.len() as u64);
+    for (rule, count) in &excluded.identities {
+        body.string(rule);
+        body.uint(*count as u64);
+    }
+    body.bytes.push(match excluded.stopped {
+        None => 0,
+        Some(Stop::StartDate) => 1,
+        Some(Stop::MaxCommits) => 2,
+    });
+
+    body.uint(analysis.commits.len() as u64);
+    for commit in &analysis.commits {
+        body.bytes.extend_from_slice(commit.id.as_bytes());
+        body.int(commit.time);
+        body.string(&commit.author);
+        body.string(&commit.summary);
+    }
+    body.uint(analysis.tips.len() as u64);
+    for tip in &analysis.tips {
+        body.bytes.extend_from_slice(tip.as_bytes());
+    }
+
+    body.uint(analysis.changesets.len() as u64);
+    for changeset in &analysis.changesets {
+        body.int(changeset.time);
+        body.uint(changeset.files.len() as u64);
+        for file in &changeset.files {
+            body.path(&file.name);
+            body.status(file.status);
+        }
+    }
+
+    let mut renames: Vec<(&String, &String)> = analysis.renames.iter().collect();
+    renames.sort();
+    body.uint(renames.len() as u64);
+    for (old, current) in renames {
+        body.path(old);
+        body.path(current);
+    }
+
+    let graph = &analysis.graph.graph;
+    body.uint(analysis.graph.changesets as u64);
+    body.uint(graph.node_count() as u64);
+    for file in graph.node_weights() {
+        body.path(&file.name);
+        body.status(file.status);
+        body.uint(file.commits as u64);
+        body.int(file.first_change);
+        body.int(file.last_change);
+    }
+    body.uint(graph.edge_count() as u64);
+    for edge in graph.edge_references() {
+        let co_change = edge.weight();
+        body.uint(edge.source().index() as u64);
+        body.uint(edge.target().index() as u64);
+        body.uint(co_change.count as u64);
+        body.float(co_change.weight);
+        body.float(co_change.normalised);
+        body.uint(co_change.times.len() as u64);
+        for time in &co_change.times {
+            body.int(*time);
+        }
+    }
+
+    let mut file = Encoder::default();
+    file.bytes.extend_from_slice(MAGIC);
+    file.bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    file.uint(body.paths.len() as u64);
+    for path in &body.paths {
+        file.string(path);
+    }
+    file.bytes.extend_from_slice(&body.bytes);
+    file.bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<Analysis, Invalid> {
+    if bytes.len() < MAGIC.len() + 4 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Invalid::Data("it doesn't start like one".to_string()));
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+    let version = u32::from_le_bytes(version);
+    if version != FORMAT_VERSION {
+        return Err(Invalid::Version(version));
+    }
+
+    let mut body = Decoder {
+        bytes,
+        pos: MAGIC.len() + 4,
+        paths: Vec::new(),
+    };
+    for _ in 0..body.len()? {
+        let path = body.string()?;
+        body.paths.push(path);
+    }
+
+    let options = GraphOptions {
+        changeset_limit: body.uint()? as usize,
+        oversized: match body.byte()? {
+            0 => Oversized::Drop,
+            1 => Oversized::Keep,
+            2 => Oversized::DownWeight,
+            other => {
+                return Err(Invalid::Data(format!(
+                    "unknown oversized handling {}",
+                    other
+                )))
+            }
+        },
+    };
+    let oversized = body.uint()? as usize;
+    let filter = decode_filter(&mut body)?;
+
+    let mut excluded = Exclusions {
+        after_end: body.uint()? as usize,
+        unselected: body.uint()? as usize,
+        by_message: body.uint()? as usize,
+        ignored_revs: body.uint()? as usize,
+        ..Exclusions::default()
+    };
+    for _ in 0..body.len()? {
+        let rule = body.string()?;
+        excluded.identities.insert(rule, body.uint()? as usize);
+    }
+    excluded.stopped = match body.byte()? {
+        0 => None,
+        1 => Some(Stop::StartDate),
+        2 => Some(Stop::MaxCommits),
+        other => return Err(Invalid::Data(format!("unknown end of the walk {}", other))),
+    };
+
+    let mut commits = Vec::new();
+    for _ in 0..body.len()? {
+        commits.push(CommitInfo {
+            id: Oid::from_bytes(body.take(20)?).unwrap(),
+            time: body.int()?,
+            author: body.string()?,
+            summary: body.string()?,
+        });
+    }
+
+    let mut tips = Vec::new();
+    for _ in 0..body.len()? {
+        tips.push(Oid::from_bytes(body.take(20)?).unwrap());
+    }
+
+    let mut changesets = Vec::new();
+    for _ in 0..body.len()? {
+        let time = body.int()?;
+        let mut files = Vec::new();
+        for _ in 0..body.len()? {
+            files.push(GitFile {
+                name: body.path()?,
+                status: body.status()?,
+            });
+        }
+        changesets.push(ChangeSet { time, files });
+    }
+
+    let mut renames = RenameMap::default();
+    for _ in 0..body.len()? {
+        let old = body.path()?;
+        renames.insert(old, body.path()?);
+    }
+
+    let mut graph = GitGraph {
+        changesets: body.uint()? as u32,
+        ..GitGraph::default()
+    };
+    for _ in 0..body.len()? {
+        let file = FileNode {
+            name: body.path()?,
+            status: body.status()?,
+            commits: body.uint()? as u32,
+            first_change: body.int()?,
+            last_change: body.int()?,
+        };
+        let name = file.name.clone();
+        let idx = graph.graph.add_node(file);
+        graph.name_table.insert(name, idx);
+    }
+    let nodes = graph.graph.node_count();
+    for _ in 0..body.len()? {
+        let (source, target) = (body.uint()? as usize, body.uint()? as usize);
+        if source >= nodes || target >= nodes {
+            return Err(Invalid::Data("edge between unknown files".to_string()));
+        }
+        let mut co_change = CoChange {
+            count: body.uint()? as u32,
+            weight: body.float()?,
+            normalised: body.float()?,
+            times: Vec::new(),
+        };
+        for _ in 0..body.len()? {
+            co_change.times.push(body.int()?);
+        }
+        graph
+            .graph
+            .add_edge(NodeIndex::new(source), NodeIndex::new(target), co_change);
+    }
+    graph.fold_renames(&renames);
+    if body.pos != bytes.len() {
+        return Err(Invalid::Data("trailing bytes".to_string()));
+    }
+
+    Ok(Analysis {
+        graph,
+        changesets,
+        renames,
+        commits,
+        tips,
+        excluded,
+        oversized,
+        filter,
+        options,
+    })
+}
+
+fn encode_filter(body: &mut Encoder, filter: &GitFilter) {
+    for date in [filter.start_date, filter.end_date] {
+        body.flag(date.is_some());
+        if let Some(date) = date {
+            body.int(date.timestamp());
+        }
+    }
+    body.strings(&filter.revisions);
+    body.flag(filter.max_commits.is_some());
+    if let Some(max) = filter.max_commits {
+        body.uint(max as u64);
+    }
+    let (include, exclude) = filter.path_filter.patterns();
+    body.strings(include);
+    body.strings(exclude);
+    body.flag(filter.skip_ignored);
+    body.flag(filter.merge_diff == MergeDiff::MergeBase);
+    match &filter.selection {
+        CommitSelection::All => body.bytes.push(0),
+        CommitSelection::Merges => body.bytes.push(1),
+        CommitSelection::FirstParent => body.bytes.push(2),
+        CommitSelection::NonMerges => body.bytes.push(3),
+        CommitSelection::PullRequests(pattern) => {
+            body.bytes.push(4);
+            body.string(pattern.as_str());
+        }
+    }
+    body.uint(filter.identities.len() as u64);
+    for rule in &filter.identities {
+        body.flag(rule.role == Role::Committer);
+        body.string(rule.pattern.as_str());
+        body.flag(rule.exclude);
+    }
+    body.strings(filter.message_excludes.patterns());
+    body.flag(filter.ticket_pattern.is_some());
+    if let Some(pattern) = &filter.ticket_pattern {
+        body.string(pattern.as_str());
+    }
+    body.flag(filter.author_window.is_some());
+    if let Some(window) = filter.author_window {
+        body.int(window);
+    }
+    body.flag(filter.ignore_revs_file.is_some());
+    if let Some(file) = &filter.ignore_revs_file {
+        body.string(&file.to_string_lossy());
+    }
+}
+
+fn decode_filter(body: &mut Decoder) -> Result<GitFilter, Invalid> {
+    let mut dates = [None, None];
+    for date in &mut dates {
+        if body.flag()? {
+            let time = body.int()?;
+            *date = Some(
+                Utc.timestamp_opt(time, 0)
+                    .single()
+                    .ok_or_else(|| Invalid::Data(format!("date out of range {}", time)))?,
+            );
+        }
+    }
+    let [start_date, end_date] = dates;
+    let revisions = body.strings()?;
+    let max_commits = match body.flag()? {
+        true => Some(body.uint()? as u32),
+        false => None,
+    };
+    let as_regexes = |patterns: Vec<String>| -> Vec<String> {
+        patterns
+            .into_iter()
+            .map(|pattern| format!("re:{}", pattern))
+            .collect()
+    };
+    let include = as_regexes(body.strings()?);
+    let exclude = as_regexes(body.strings()?);
+    let path_filter = PathFilter::new(&include, &exclude).map_err(|_| invalid_regex())?;
+    let skip_ignored = body.flag()?;
+    let merge_diff = match body.flag()? {
+        true => MergeDiff::MergeBase,
+        false => MergeDiff::FirstParent,
+    };
+    let selection = match body.byte()? {
+        0 => CommitSelection::All,
+        1 => CommitSelection::Merges,
+        2 => CommitSelection::FirstParent,
+        3 => CommitSelection::NonMerges,
+        4 => CommitSelection::PullRequests(body.regex()?),
+        other => return Err(Invalid::Data(format!("unknown commit selection {}", other))),
+    };
+    let mut identities = Vec::new();
+    for _ in 0..body.len()? {
+        identities.push(IdentityRule {
+            role: match body.flag()? {
+                true => Role::Committer,
+                false => Role::Author,
+            },
+            pattern: body.regex()?,
+            exclude: body.flag()?,
+        });
+    }
+    let message_excludes = RegexSet::new(body.strings()?).map_err(|_| invalid_regex())?;
+    let ticket_pattern = match body.flag()? {
+        true => Some(body.regex()?),
+        false => None,
+    };
+    let author_window = match body.flag()? {
+        true => Some(body.int()?),
+        false => None,
+    };
+    let ignore_revs_file = match body.flag()? {
+        true => Some(PathBuf::from(body.string()?)),
+        false => None,
+    };
+
+    Ok(GitFilter {
+        start_date,
+        end_date,
+        revisions,
+        max_commits,
+        path_filter,
+        skip_ignored,
+        merge_diff,
+        selection,
+        identities,
+        message_excludes,
+        ticket_pattern,
+        author_window,
+        ignore_revs_file,
+    })
+}
+
+fn invalid_regex() -> Invalid {
+    Invalid::Data("invalid regex".to_string())
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+    /// The table of paths, in the order they were first written.
+    paths: Vec<String>,
+    indices: FxHashMap<String, u64>,
+}
+
+impl Encoder {
+    fn uint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn int(&mut self, value: i64) {
+        self.uint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn float(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.uint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn flag(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    fn strings<S: AsRef<str>>(&mut self, values: &[S]) {
+        self.uint(values.len() as u64);
+        for value in values {
+            self.string(value.as_ref());
+        }
+    }
+
+    fn path(&mut self, path: &str) {
+        let index = match self.indices.get(path) {
+            Some(index) => *index,
+            None => {
+                let index = self.paths.len() as u64;
+                self.paths.push(path.to_string());
+                self.indices.insert(path.to_string(), index);
+                index
+            }
+        };
+        self.uint(index);
+    }
+
+    fn status(&mut self, status: Status) {
+        self.bytes.push(match status {
+            Status::Added => 0,
+            Status::Deleted => 1,
+            Status::Modified => 2,
+            Status::Copied => 3,
+        });
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    paths: Vec<String>,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Invalid> {
+        if self.bytes.len() - self.pos < n {
+            return Err(Invalid::Data("it ends too early".to_string()));
+        }
+        self.pos += n;
+        Ok(&self.bytes[self.pos - n..self.pos])
+    }
+
+    fn byte(&mut self) -> Result<u8, Invalid> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, Invalid> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(Invalid::Data("overlong number".to_string()))
+    }
+
+    /// A count of items that follow, each at least a byte long.
+    fn len(&mut self) -> Result<usize, Invalid> {
+        let len = self.uint()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return Err(Invalid::Data("it ends too early".to_string()));
+        }
+        Ok(len as usize)
+    }
+
+    fn int(&mut self) -> Result<i64, Invalid> {
+        let value = self.uint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn float(&mut self) -> Result<f64, Invalid> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<String, Invalid> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| Invalid::Data("text that isn't UTF-8".to_string()))
+    }
+
+    fn flag(&mut self) -> Result<bool, Invalid> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(Invalid::Data(format!("unknown flag {}", other))),
+        }
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, Invalid> {
+        let mut values = Vec::new();
+        for _ in 0..self.len()? {
+            values.push(self.string()?);
+        }
+        Ok(values)
+    }
+
+    fn regex(&mut self) -> Result<Regex, Invalid> {
+        Regex::new(&self.string()?).map_err(|_| invalid_regex())
+    }
+
+    fn path(&mut self) -> Result<String, Invalid> {
+        let index = self.uint()? as usize;
+        self.paths
+            .get(index)
+            .cloned()
+            .ok_or_else(|| Invalid::Data("unknown path".to_string()))
+    }
+
+    fn status(&mut self) -> Result<Status, Invalid> {
+        match self.byte()? {
+            0 => Ok(Status::Added),
+            1 => Ok(Status::Deleted),
+            2 => Ok(Status::Modified),
+            3 => Ok(Status::Copied),
+            other => Err(Invalid::Data(format!("unknown status {}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::git2graph::load_fixture_repo;
+#[cfg(test)]
+use super::scan::Scan;
+
+#[cfg(test)]
+fn scanned() -> Analysis {
+    let (dir, _repo) = load_fixture_repo("merge-repo").unwrap();
+    Scan::new(dir.path())
+        .selection(CommitSelection::NonMerges)
+        .path_filter(PathFilter::new(&["*"], &["re:^d$"]).unwrap())
+        .identity(IdentityRule {
+            role: Role::Committer,
+            pattern: Regex::new("Doe").unwrap(),
+            exclude: false,
+        })
+        .ticket_pattern(Regex::new(r"#(\d+)").unwrap())
+        .changeset_limit(3)
+        .oversized(Oversized::DownWeight)
+        .run()
+        .unwrap()
+}
+
+#[test]
+fn test_round_trip() {
+    let analysis = scanned();
+    let bytes = encode(&analysis);
+    let loaded = decode(&bytes).unwrap();
+
+    assert_eq!(encode(&loaded), bytes);
+    assert_eq!(loaded.commits, analysis.commits);
+    assert_eq!(loaded.tips, analysis.tips);
+    assert!(loaded.filter.path_filter.matches("a"));
+    assert!(!loaded.filter.path_filter.matches("d"));
+    assert_eq!(loaded.excluded, analysis.excluded);
+    assert_eq!(loaded.changesets.len(), analysis.changesets.len());
+    assert_eq!(loaded.graph.name_table, analysis.graph.name_table);
+    let graph = &loaded.graph.graph;
+    assert_eq!(graph.node_count(), analysis.graph.graph.node_count());
+    for edge in graph.edge_indices() {
+        assert_eq!(
+            graph.edge_endpoints(edge),
+            analysis.graph.graph.edge_endpoints(edge)
+        );
+        assert_eq!(graph[edge], analysis.graph.graph[edge]);
+    }
+}
+
+#[test]
+fn test_varints() {
+    let mut encoder = Encoder::default();
+    let values = [0, 1, -1, 63, -64, 64, i64::MAX, i64::MIN];
+    for value in values {
+        encoder.int(value);
+    }
+    encoder.uint(u64::MAX);
+    let mut decoder = Decoder {
+        bytes: &encoder.bytes,
+        pos: 0,
+        paths: Vec::new(),
+    };
+
+    for value in values {
+        assert_eq!(decoder.int().unwrap(), value);
+    }
+    assert_eq!(decoder.uint().unwrap(), u64::MAX);
+    assert_eq!(decoder.pos, encoder.bytes.len());
+}
+
+#[test]
+fn test_rejects_other_files() {
+    let mut bytes = encode(&scanned());
+
+    assert!(matches!(
+        decode(&bytes[..bytes.len() - 1]),
+        Err(Invalid::Data(_))
+    ));
+    assert!(matches!(decode(b"graph {}\n"), Err(Invalid::Data(_))));
+    bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&2u32.to_le_bytes());
+    assert_eq!(decode(&bytes).err(), Some(Invalid::Version(2)));
+
+    let mut encoder = Encoder::default();
+    encoder.flag(true);
+    encoder.int(i64::MAX);
+    let mut decoder = Decoder {
+        bytes: &encoder.bytes,
+        pos: 0,
+        paths: Vec::new(),
+    };
+    assert!(matches!(decode_filter(&mut decoder), Err(Invalid::Data(_))));
+}
+
+#[test]
+fn test_save_replaces_scan() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("repo.scan");
+    fs::write(&path, b"an older scan").unwrap();
+
+    let analysis = scanned();
+    save(&analysis, &path).unwrap();
+
+    assert_eq!(fs::read(&path).unwrap(), encode(&analysis));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    assert!(save(&analysis, &dir.path().join("missing/repo.scan")).is_err());
+}